@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use utils::{Profile, Closure};
+
+#[derive(RustcDecodable, Debug)]
+struct Config {
+    profile: HashMap<String, Profile>,
+}
+
+/// Reads the named profile from a TOML file with [profile.<name>] sections.
+/// Without a file, the built-in defaults are used for the "default" profile.
+pub fn read_profile(fname: &str, name: &str) -> Profile {
+    use std::io::Read;
+    let mut f = match std::fs::File::open(fname) {
+        Ok(f) => f,
+        Err(e) => {
+            if name == "default" { return Default::default() }
+            panic!("Could not open {}: {}", fname, e);
+        }
+    };
+    let mut s = String::new();
+    f.read_to_string(&mut s).unwrap();
+    let c: Config = ::toml::decode_str(&s).unwrap_or_else(|| panic!("Could not parse {}", fname));
+    c.profile.get(name).cloned().unwrap_or_else(|| panic!("No profile {:?} in {}", name, fname))
+}
+
+#[derive(RustcDecodable, Debug)]
+struct ClosureEntry {
+    etapp: String,
+    from_km: f64,
+    to_km: f64,
+    from_date: String,
+    to_date: String,
+    reason: Option<String>,
+    detour: Option<Vec<Vec<f64>>>, // [lat, lon] points
+}
+
+#[derive(RustcDecodable, Debug)]
+struct ClosureFile {
+    closure: Vec<ClosureEntry>,
+}
+
+/// Reads trail closures from a TOML file with a [[closure]] section for each, e g
+///
+/// ```text
+/// [[closure]]
+/// etapp = "2_4"
+/// from_km = 3.2
+/// to_km = 5.0
+/// from_date = "2026-09-01"
+/// to_date = "2026-11-30"
+/// reason = "Avverkning"
+/// detour = [[55.912, 13.521], [55.918, 13.540]]
+/// ```
+///
+/// where reason and detour (lat, lon points) are optional. Without a file, there are no closures.
+pub fn read_closures(fname: &str) -> Vec<Closure> {
+    use std::io::Read;
+    let mut f = match std::fs::File::open(fname) { Ok(f) => f, Err(_) => return vec!() };
+    let mut s = String::new();
+    f.read_to_string(&mut s).unwrap();
+    let c: ClosureFile = ::toml::decode_str(&s).unwrap_or_else(|| panic!("Could not parse {}", fname));
+    // Closure::active compares dates as text, so they are all written out the same way.
+    let date = |d: &str| ::chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d")
+        .unwrap_or_else(|e| panic!("Bad date {:?} in {}: {}", d, fname, e)).format("%Y-%m-%d").to_string();
+    c.closure.into_iter().map(|c| Closure { etapp: c.etapp, from_km: c.from_km * 1000f64, to_km: c.to_km * 1000f64,
+        from_date: date(&c.from_date), to_date: date(&c.to_date), reason: c.reason.unwrap_or("".into()),
+        detour: c.detour.unwrap_or(vec!()).iter().map(|p| ::utils::geo::wgs84_to_rt90(p[0], p[1])).collect() }).collect()
+}
+
+/// Surface, difficulty and features along (part of) an etapp.
+#[derive(RustcDecodable, Debug, Clone)]
+pub struct Stretch {
+    pub etapp: String,
+    pub from_km: Option<f64>, // In km, from the start of the etapp if left out
+    pub to_km: Option<f64>, // To the end of the etapp if left out
+    pub surface: Option<String>, // E g "asphalt", "gravel" or "path"
+    pub difficulty: Option<i32>, // From 1 for easy to 3 for hard
+    pub features: Option<Vec<String>>, // E g ["beach"]
+}
+
+#[derive(RustcDecodable, Debug)]
+struct AttributeFile {
+    stretch: Vec<Stretch>,
+}
+
+/// Reads trail attributes from a TOML file with a [[stretch]] section for each, e g
+///
+/// ```text
+/// [[stretch]]
+/// etapp = "1_3"
+/// from_km = 2.0
+/// to_km = 4.5
+/// surface = "sand"
+/// difficulty = 2
+/// features = ["beach", "view"]
+/// ```
+///
+/// Without a file, nothing is known about the trail.
+pub fn read_attributes(fname: &str) -> Vec<Stretch> {
+    use std::io::Read;
+    let mut f = match std::fs::File::open(fname) { Ok(f) => f, Err(_) => return vec!() };
+    let mut s = String::new();
+    f.read_to_string(&mut s).unwrap();
+    let a: AttributeFile = ::toml::decode_str(&s).unwrap_or_else(|| panic!("Could not parse {}", fname));
+    a.stretch
+}
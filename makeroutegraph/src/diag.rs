@@ -0,0 +1,214 @@
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap};
+use std::cmp;
+use petgraph::Graph;
+use petgraph::graph::NodeIndex;
+use utils::spatial::Grid;
+use {Node, Edge};
+
+#[derive(RustcEncodable, Debug)]
+struct Component {
+    etapper: Vec<String>,
+    km: f64, // Trail length
+    access_points: usize,
+}
+
+#[derive(RustcEncodable, Debug)]
+struct Bridge {
+    etapp: String,
+    from_km: f64,
+    to_km: f64,
+}
+
+#[derive(RustcEncodable, Debug)]
+struct NoStop {
+    etapp: String,
+    nearest_km: Option<f64>, // Distance to the closest stop area, None if no stop area is linked to the trail
+}
+
+#[derive(RustcEncodable, Debug)]
+struct Gap {
+    from_etapp: String, // At an access point, or where the trail ends
+    from_km: f64,
+    to_etapp: String,
+    to_km: f64,
+    km: f64, // Length along the trail, which may run over several etapper
+}
+
+#[derive(RustcEncodable, Debug)]
+struct Diagnostics {
+    profile: String,
+    components: Vec<Component>,
+    bridges: Vec<Bridge>,
+    no_stop: Vec<NoStop>,
+    gaps: Vec<Gap>, // Trail stretches without access to a stop area, longest first
+}
+
+fn km(n: &Node) -> f64 { n.etapp.as_ref().unwrap().1 / 1000f64 }
+
+// Edges on the trail, i e not access walks, as (neighbour, edge) per node.
+fn trail_adj(graph: &Graph<Node, Edge>) -> Vec<Vec<(usize, usize)>> {
+    let mut adj = vec![vec!(); graph.node_count()];
+    for (i, e) in graph.raw_edges().iter().enumerate() {
+        if graph[e.source()].etapp.is_none() || graph[e.target()].etapp.is_none() { continue; }
+        adj[e.source().index()].push((e.target().index(), i));
+        adj[e.target().index()].push((e.source().index(), i));
+    }
+    adj
+}
+
+// Distance along the trail from the closest access node, and that node, for every node reached.
+// Also which edges nodes were reached through.
+fn from_access(graph: &Graph<Node, Edge>, adj: &Vec<Vec<(usize, usize)>>, access: &[usize]) -> (Vec<Option<(f64, usize)>>, Vec<bool>) {
+    let mut best: Vec<Option<(f64, usize)>> = vec![None; adj.len()];
+    let mut via = vec![None; adj.len()];
+    let mut heap = BinaryHeap::new();
+    for &a in access {
+        best[a] = Some((0f64, a));
+        heap.push((0i64, a));
+    }
+    // Max-heap on negated millimeters, so the closest node is popped first.
+    while let Some((negd, v)) = heap.pop() {
+        let (d, origin) = best[v].unwrap();
+        if -negd > (d * 1000f64) as i64 { continue; }
+        for &(w, e) in &adj[v] {
+            let nd = d + graph.raw_edges()[e].weight.dist;
+            if best[w].map(|(od, _)| od <= nd).unwrap_or(false) { continue; }
+            best[w] = Some((nd, origin));
+            via[w] = Some(e);
+            heap.push((-((nd * 1000f64) as i64), w));
+        }
+    }
+    let mut tree = vec![false; graph.edge_count()];
+    for e in via.into_iter().filter_map(|e| e) { tree[e] = true; }
+    (best, tree)
+}
+
+// Tarjan's bridge finding, without recursion.
+fn bridges(adj: &Vec<Vec<(usize, usize)>>) -> Vec<usize> {
+    let n = adj.len();
+    let (mut disc, mut low) = (vec![0; n], vec![0; n]);
+    let mut timer = 1;
+    let mut r = vec!();
+    for s in 0..n {
+        if disc[s] != 0 || adj[s].len() == 0 { continue; }
+        disc[s] = timer; low[s] = timer; timer += 1;
+        let mut stack = vec!((s, usize::max_value(), 0));
+        loop {
+            let (v, pe, i) = match stack.last() { Some(&x) => x, None => break };
+            if i < adj[v].len() {
+                stack.last_mut().unwrap().2 += 1;
+                let (w, e) = adj[v][i];
+                if e == pe { continue; }
+                if disc[w] == 0 {
+                    disc[w] = timer; low[w] = timer; timer += 1;
+                    stack.push((w, e, 0));
+                } else { low[v] = cmp::min(low[v], disc[w]); }
+            } else {
+                stack.pop();
+                if let Some(&(u, _, _)) = stack.last() {
+                    low[u] = cmp::min(low[u], low[v]);
+                    if low[v] > disc[u] { r.push(pe); }
+                }
+            }
+        }
+    }
+    r
+}
+
+/// Reports connected components, bridges, etapper far from any stop area linked to the
+/// trail and long stretches of trail without access to a stop area, across etapp borders.
+/// Returns the contents of diagnostics.txt and diagnostics.json.
+pub fn diagnose(graph: &Graph<Node, Edge>, etapper: &BTreeMap<String, Vec<(f64, f64)>>, profile: &str, no_stop_dist: f64) -> (String, String) {
+    use std::fmt::Write;
+    let adj = trail_adj(graph);
+    let is_access = |i: usize| graph.neighbors_undirected(NodeIndex::new(i)).any(|nn| graph[nn].stoparea.is_some());
+    let order = |i: usize| { let n = &graph.raw_nodes()[i].weight; (::utils::etapp_number(n.etapp_name()), n.etapp_name().to_string(), km(n)) };
+
+    // Connected components of the trail
+    let mut seen = vec![false; adj.len()];
+    let mut components = vec!();
+    let mut unreached = vec!(); // First and last node, and length, of components without access
+    for s in (0..adj.len()).filter(|&s| graph.raw_nodes()[s].weight.etapp.is_some()) {
+        if seen[s] { continue; }
+        seen[s] = true;
+        let (mut todo, mut etapps, mut length, mut access) = (vec!(s), BTreeSet::new(), 0f64, 0);
+        let (mut lo, mut hi) = (s, s);
+        while let Some(v) = todo.pop() {
+            etapps.insert(graph.raw_nodes()[v].weight.etapp_name().to_string());
+            if is_access(v) { access += 1; }
+            if order(v) < order(lo) { lo = v; }
+            if order(v) > order(hi) { hi = v; }
+            for &(w, e) in &adj[v] {
+                if v < w { length += graph.raw_edges()[e].weight.dist; }
+                if !seen[w] { seen[w] = true; todo.push(w); }
+            }
+        }
+        if access == 0 { unreached.push((lo, hi, length)); }
+        components.push(Component { etapper: etapps.into_iter().collect(), km: length / 1000f64, access_points: access });
+    }
+    components.sort_by(|a, b| b.km.partial_cmp(&a.km).unwrap());
+
+    let bridges: Vec<Bridge> = bridges(&adj).into_iter().map(|e| {
+        let e = &graph.raw_edges()[e];
+        let (a, b) = (&graph[e.source()], &graph[e.target()]);
+        Bridge { etapp: a.etapp_name().into(), from_km: km(a), to_km: if a.etapp_name() == b.etapp_name() { km(b) } else { km(a) } }
+    }).collect();
+
+    // Etapper without any stop area close by. Only stop areas in the graph count, not those excluded or out of reach.
+    let sa_index = Grid::from_iter(2000f64, graph.node_indices()
+        .filter(|&ni| graph[ni].stoparea.is_some() && graph.neighbors_undirected(ni).next().is_some())
+        .map(|ni| (graph[ni].pos, graph[ni].stoparea.unwrap())));
+    let mut no_stop = vec!();
+    for (name, v) in etapper {
+        let nearest = v.iter().filter_map(|&p| sa_index.closest(p, |_| true).map(|x| x.1)).fold(None, |a: Option<f64>, b| Some(a.map_or(b, |a| a.min(b))));
+        if nearest.map_or(true, |d| d > no_stop_dist) { no_stop.push(NoStop { etapp: name.clone(), nearest_km: nearest.map(|d| d / 1000f64) }); }
+    }
+    no_stop.sort_by(|a, b| a.etapp.cmp(&b.etapp));
+
+    // Stretches of trail between access points. Every edge not on the way from the closest access point
+    // to either of its ends is where walks from two access points meet, and trail ends are reached from one.
+    let access: Vec<usize> = (0..adj.len()).filter(|&i| graph.raw_nodes()[i].weight.etapp.is_some() && is_access(i)).collect();
+    let (best, tree) = from_access(graph, &adj, &access);
+    let at = |i: usize| { let n = &graph.raw_nodes()[i].weight; (n.etapp_name().to_string(), km(n)) };
+    let mut gaps = vec!();
+    for (i, e) in graph.raw_edges().iter().enumerate() {
+        if tree[i] || graph[e.source()].etapp.is_none() || graph[e.target()].etapp.is_none() { continue; }
+        let (a, b) = match (best[e.source().index()], best[e.target().index()]) { (Some(a), Some(b)) => (a, b), _ => continue };
+        let ((fe, fk), (te, tk)) = (at(a.1), at(b.1));
+        gaps.push(Gap { from_etapp: fe, from_km: fk, to_etapp: te, to_km: tk, km: (a.0 + e.weight.dist + b.0) / 1000f64 });
+    }
+    for i in (0..adj.len()).filter(|&i| adj[i].len() == 1) {
+        let (d, origin) = match best[i] { Some(x) if x.0 > 0f64 => x, _ => continue };
+        let ((fe, fk), (te, tk)) = (at(origin), at(i));
+        gaps.push(Gap { from_etapp: fe, from_km: fk, to_etapp: te, to_km: tk, km: d / 1000f64 });
+    }
+    // Trail without any access at all, as a whole
+    for &(lo, hi, length) in &unreached {
+        let ((fe, fk), (te, tk)) = (at(lo), at(hi));
+        gaps.push(Gap { from_etapp: fe, from_km: fk, to_etapp: te, to_km: tk, km: length / 1000f64 });
+    }
+    gaps.sort_by(|a, b| b.km.partial_cmp(&a.km).unwrap());
+    gaps.truncate(20);
+
+    let mut f = String::new();
+    write!(f, "Profile: {}\n\n{} connected components:\n", profile, components.len()).unwrap();
+    for c in &components {
+        write!(f, "  {:.1} km, {} access points: {}\n", c.km, c.access_points, c.etapper.join(", ")).unwrap();
+    }
+    write!(f, "\n{} bridges, the network splits without them:\n", bridges.len()).unwrap();
+    for b in &bridges { write!(f, "  {} km {:.1} - {:.1}\n", b.etapp, b.from_km, b.to_km).unwrap(); }
+    write!(f, "\n{} etapper without a stop area within {:.1} km:\n", no_stop.len(), no_stop_dist / 1000f64).unwrap();
+    for n in &no_stop {
+        match n.nearest_km {
+            Some(d) => write!(f, "  {}, nearest is {:.1} km away\n", n.etapp, d).unwrap(),
+            None => write!(f, "  {}, no stop area is linked to the trail\n", n.etapp).unwrap(),
+        }
+    }
+    write!(f, "\nLongest stretches without access to a stop area:\n").unwrap();
+    for g in &gaps { write!(f, "  {:.1} km, from {} km {:.1} to {} km {:.1}\n", g.km, g.from_etapp, g.from_km, g.to_etapp, g.to_km).unwrap(); }
+
+    println!("{} components, {} bridges, {} etapper without stop areas, longest stretch without access {:.1} km",
+        components.len(), bridges.len(), no_stop.len(), gaps.first().map(|g| g.km).unwrap_or(0f64));
+    let d = Diagnostics { profile: profile.into(), components: components, bridges: bridges, no_stop: no_stop, gaps: gaps };
+    (f, ::rustc_serialize::json::encode(&d).unwrap())
+}
@@ -0,0 +1,154 @@
+use std::collections::BTreeMap;
+use petgraph::Graph;
+use petgraph::graph::NodeIndex;
+use utils::spatial::Grid;
+use {Node, Edge, add_node, dist};
+
+fn mid(a: (f64, f64), b: (f64, f64)) -> (f64, f64) { ((a.0 + b.0) / 2f64, (a.1 + b.1) / 2f64) }
+
+// Where segment p1-p2 crosses q1-q2, as fractions along each segment.
+fn intersect(p1: (f64, f64), p2: (f64, f64), q1: (f64, f64), q2: (f64, f64)) -> Option<(f64, f64)> {
+    let r = (p2.0 - p1.0, p2.1 - p1.1);
+    let s = (q2.0 - q1.0, q2.1 - q1.1);
+    let denom = r.0 * s.1 - r.1 * s.0;
+    if denom.abs() < 1e-9 { return None; } // Parallel
+    let qp = (q1.0 - p1.0, q1.1 - p1.1);
+    let t = (qp.0 * s.1 - qp.1 * s.0) / denom;
+    let u = (qp.0 * r.1 - qp.1 * r.0) / denom;
+    if t > 0f64 && t < 1f64 && u > 0f64 && u < 1f64 { Some((t, u)) } else { None }
+}
+
+// Distance from p to the segment a-b, and how far along it the closest point is, from 0 to 1.
+fn to_segment(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> (f64, f64) {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let l2 = dx * dx + dy * dy;
+    let t = if l2 <= 0f64 { 0f64 } else { (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / l2).max(0f64).min(1f64) };
+    (dist(p, (a.0 + t * dx, a.1 + t * dy)), t)
+}
+
+// Edges along one etapp, as (source, target).
+fn etapp_edges(graph: &Graph<Node, Edge>) -> Vec<(NodeIndex, NodeIndex)> {
+    graph.raw_edges().iter()
+        .filter(|e| graph[e.source()].etapp.is_some() && graph[e.target()].etapp.is_some())
+        .filter(|e| graph[e.source()].etapp_name() == graph[e.target()].etapp_name())
+        .map(|e| (e.source(), e.target())).collect()
+}
+
+fn split_node(graph: &mut Graph<Node, Edge>, (a, b): (NodeIndex, NodeIndex), t: f64) -> NodeIndex {
+    let (pa, pb) = (graph[a].pos, graph[b].pos);
+    let (ka, kb) = (graph[a].etapp.as_ref().unwrap().1, graph[b].etapp.as_ref().unwrap().1);
+    let name = graph[a].etapp_name().to_string();
+    add_node(graph, (pa.0 + t * (pb.0 - pa.0), pa.1 + t * (pb.1 - pa.1)), &name, ka + t * (kb - ka))
+}
+
+// Replaces every edge in splits, by index into edges, with a chain through the nodes added along it.
+// The nodes are given with how far along the edge they are, from 0 to 1.
+fn split_edges(graph: &mut Graph<Node, Edge>, edges: &[(NodeIndex, NodeIndex)], splits: BTreeMap<usize, Vec<(f64, NodeIndex)>>) {
+    for (i, mut v) in splits {
+        let (a, b) = edges[i];
+        let old = match graph.find_edge(a, b) { Some(e) => graph.remove_edge(e).unwrap(), None => continue };
+        v.sort_by(|x, y| x.0.partial_cmp(&y.0).unwrap());
+        let (mut prev, mut prevt) = (a, 0f64);
+        for (t, n) in v.into_iter().chain(Some((1f64, b))) {
+            // Climb and walking time are spread evenly along the old edge.
+            let km = Some((graph[prev].etapp.as_ref().unwrap().1, graph[n].etapp.as_ref().unwrap().1));
+            let e = Edge { dist: dist(graph[prev].pos, graph[n].pos), km: km, .. old.part(t - prevt) };
+            graph.add_edge(prev, n, e);
+            prev = n;
+            prevt = t;
+        }
+    }
+}
+
+/// Adds a junction wherever two etapper cross each other mid-track.
+/// Both edges are split at the crossing, and the two new nodes are linked.
+/// Returns the number of crossings found.
+pub fn add_crossings(graph: &mut Graph<Node, Edge>, max_edge: f64) -> usize {
+    let edges = etapp_edges(graph);
+    let index = Grid::from_iter(500f64, edges.iter().enumerate().map(|(i, &(a, b))| (mid(graph[a].pos, graph[b].pos), i)));
+
+    let mut crossings = vec!();
+    for (i, &(a, b)) in edges.iter().enumerate() {
+        let (pa, pb) = (graph[a].pos, graph[b].pos);
+        // No edge is longer than max_edge, so no crossing edge has its midpoint further away than this.
+        for (j, _) in index.within(mid(pa, pb), dist(pa, pb) / 2f64 + max_edge / 2f64) {
+            if j <= i { continue; }
+            let (c, d) = edges[j];
+            if graph[c].etapp_name() == graph[a].etapp_name() { continue; }
+            if let Some((t, u)) = intersect(pa, pb, graph[c].pos, graph[d].pos) { crossings.push((i, t, j, u)); }
+        }
+    }
+
+    let mut splits: BTreeMap<usize, Vec<(f64, NodeIndex)>> = BTreeMap::new();
+    for &(i, t, j, u) in &crossings {
+        let ja = split_node(graph, edges[i], t);
+        let jb = split_node(graph, edges[j], u);
+        println!("{} crosses {} at km {:.1}", graph[ja].etapp_name(), graph[jb].etapp_name(), graph[ja].etapp.as_ref().unwrap().1 / 1000f64);
+        graph.add_edge(ja, jb, Edge::new(0f64));
+        splits.entry(i).or_insert(vec!()).push((t, ja));
+        splits.entry(j).or_insert(vec!()).push((u, jb));
+    }
+
+    split_edges(graph, &edges, splits);
+    crossings.len()
+}
+
+/// Where two etapper run along the same path (closer than width), keep the
+/// edges of only one of them, and link the other at both ends of the shared
+/// stretch. The etapp whose name sorts first is kept. Distances are measured
+/// from the track points of one etapp to the segments of the other, so the
+/// points need not be close to each other.
+/// Returns the number of edges removed.
+pub fn merge_corridors(graph: &mut Graph<Node, Edge>, width: f64, max_edge: f64) -> usize {
+    let edges = etapp_edges(graph);
+    let index = Grid::from_iter(500f64, edges.iter().enumerate().map(|(i, &(a, b))| (mid(graph[a].pos, graph[b].pos), i)));
+
+    // The closest segment of another etapp for every point, as (segment, how far along it, distance).
+    let mut shared: BTreeMap<NodeIndex, (usize, f64, f64)> = BTreeMap::new();
+    for ni in graph.node_indices().filter(|&ni| graph[ni].etapp.is_some()) {
+        let own = graph[ni].etapp_name();
+        let mut best: Option<(usize, f64, f64)> = None;
+        // No segment is longer than max_edge, so none within width has its midpoint further away than this.
+        for (j, _) in index.within(graph[ni].pos, width + max_edge / 2f64) {
+            let (a, b) = edges[j];
+            let other = graph[a].etapp_name();
+            if other >= own { continue; }
+            let (d, t) = to_segment(graph[ni].pos, graph[a].pos, graph[b].pos);
+            if d > width { continue; }
+            // Prefer the etapp that sorts first, so that corridors shared by three etapper merge onto the same one.
+            if best.map(|(bj, _, bd)| {
+                let kept = graph[edges[bj].0].etapp_name();
+                other < kept || (other == kept && d < bd)
+            }).unwrap_or(true) { best = Some((j, t, d)); }
+        }
+        if let Some(b) = best { shared.insert(ni, b); }
+    }
+
+    let removed: Vec<(NodeIndex, NodeIndex)> = edges.iter().cloned()
+        .filter(|&(a, b)| shared.contains_key(&a) && shared.contains_key(&b)).collect();
+    for &(a, b) in &removed {
+        if let Some(e) = graph.find_edge(a, b) { graph.remove_edge(e); }
+    }
+
+    // Nodes left without edges are inside the shared stretch; the others are at its ends.
+    // They link to the closest point of the kept etapp, a new node unless within a meter of a track point.
+    let mut splits: BTreeMap<usize, Vec<(f64, NodeIndex)>> = BTreeMap::new();
+    for (&ni, &(j, t, _)) in &shared {
+        if graph.neighbors_undirected(ni).count() == 0 { continue; }
+        let (a, b) = edges[j];
+        let l = dist(graph[a].pos, graph[b].pos);
+        let nn = if t * l < 1f64 { a } else if (1f64 - t) * l < 1f64 { b }
+            else if graph.find_edge(a, b).is_none() { if t < 0.5f64 { a } else { b } }
+            else {
+                let n = split_node(graph, (a, b), t);
+                splits.entry(j).or_insert(vec!()).push((t, n));
+                n
+            };
+        if graph.find_edge_undirected(ni, nn).is_some() { continue; }
+        let d = dist(graph[ni].pos, graph[nn].pos);
+        println!("{} joins {} at km {:.1}", graph[ni].etapp_name(), graph[nn].etapp_name(), graph[ni].etapp.as_ref().unwrap().1 / 1000f64);
+        graph.add_edge(ni, nn, Edge::new(d));
+    }
+    split_edges(graph, &edges, splits);
+    removed.len()
+}
@@ -0,0 +1,519 @@
+//! Builds the graph of the Skåneleden etapper, with the stop areas linked to it,
+//! and finds the paths worth walking between the stop areas.
+
+extern crate rustc_serialize;
+extern crate petgraph;
+extern crate utils;
+extern crate toml;
+extern crate osmpbfreader;
+extern crate chrono;
+
+pub mod config;
+pub mod osm;
+mod junctions;
+pub mod render;
+pub mod diag;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use petgraph::Graph;
+use petgraph::graph::{NodeIndex, EdgeIndex};
+
+#[derive(RustcDecodable, Default, Debug, Clone)]
+pub struct StopArea {
+    pub id: i32,
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub pos: (f64, f64),
+    pub stoparea: Option<i32>,
+    pub etapp: Option<(String, f64)>, // Etapp name and meters from its start
+}
+
+impl Node {
+    pub fn etapp_name(&self) -> &str { &self.etapp.as_ref().unwrap().0 }
+}
+
+fn dist(a: (f64, f64), b: (f64, f64)) -> f64 { ((a.0 - b.0) * (a.0 - b.0) + (a.1 - b.1) * (a.1 - b.1)).sqrt() }
+
+#[derive(Debug, Clone, Default)]
+pub struct Edge {
+    pub dist: f64,
+    pub geom: Vec<(f64, f64)>, // Walked geometry from source to target incl both ends, empty if a straight line
+    pub ascent: f64, // From source to target
+    pub descent: f64,
+    pub time_fwd: f64, // Estimated walking time in seconds, from source to target
+    pub time_back: f64,
+    pub km: Option<(f64, f64)>, // Meters along the etapp at source and target, for edges along an etapp
+    pub detour: Option<usize>, // Index into TrailGraph::closures, for detours around a closed stretch
+    pub surface: BTreeMap<String, f64>, // Meters on each known surface, see TrailGraph::add_attributes
+    pub difficulty: i32,
+    pub features: BTreeSet<String>,
+}
+
+impl Edge {
+    // A flat edge
+    fn new(d: f64) -> Edge {
+        let t = utils::route::walk_time("distance", d, 0f64);
+        Edge { dist: d, geom: vec!(), ascent: 0f64, descent: 0f64, time_fwd: t, time_back: t, .. Default::default() }
+    }
+
+    // A trail segment climbing rise meters from source to target
+    fn climb(d: f64, rise: f64, model: &str) -> Edge {
+        Edge { dist: d, geom: vec!(), ascent: rise.max(0f64), descent: (-rise).max(0f64),
+            time_fwd: utils::route::walk_time(model, d, rise), time_back: utils::route::walk_time(model, d, -rise), .. Default::default() }
+    }
+
+    // Fraction f of this edge, as a straight line.
+    fn part(&self, f: f64) -> Edge {
+        Edge { dist: self.dist * f, geom: vec!(), ascent: self.ascent * f, descent: self.descent * f,
+            time_fwd: self.time_fwd * f, time_back: self.time_back * f, km: None, detour: None,
+            surface: self.surface.iter().map(|(k, v)| (k.clone(), v * f)).collect(), difficulty: self.difficulty, features: self.features.clone() }
+    }
+
+    // This edge followed by e, which starts where this one ends.
+    fn join(self, e: Edge) -> Edge {
+        let mut geom = self.geom;
+        geom.extend(e.geom.into_iter().skip(1));
+        let mut surface = self.surface;
+        for (k, v) in e.surface { *surface.entry(k).or_insert(0f64) += v; }
+        let mut features = self.features;
+        features.extend(e.features);
+        Edge { dist: self.dist + e.dist, geom: geom, ascent: self.ascent + e.ascent, descent: self.descent + e.descent,
+            time_fwd: self.time_fwd + e.time_fwd, time_back: self.time_back + e.time_back,
+            km: match (self.km, e.km) { (Some(a), Some(b)) => Some((a.0, b.1)), _ => None }, detour: None,
+            surface: surface, difficulty: std::cmp::max(self.difficulty, e.difficulty), features: features }
+    }
+}
+
+// The edge between from and to, turned to go in that direction, with its geometry filled in.
+fn walk_edge(g: &Graph<Node, Edge>, from: NodeIndex, to: NodeIndex) -> Edge {
+    let (e, dir) = g.find_edge_undirected(from, to).unwrap();
+    let mut r = g[e].clone();
+    if r.geom.len() == 0 { r.geom = vec!(g[from].pos, g[to].pos); }
+    if dir != petgraph::EdgeDirection::Outgoing {
+        r.geom.reverse();
+        std::mem::swap(&mut r.ascent, &mut r.descent);
+        std::mem::swap(&mut r.time_fwd, &mut r.time_back);
+        r.km = r.km.map(|(a, b)| (b, a));
+    }
+    r
+}
+
+fn make_graph_file(tg: &TrailGraph) -> utils::GraphFile {
+    let graph = &tg.graph;
+    utils::GraphFile {
+        version: utils::GRAPH_VERSION,
+        profile: tg.profile_name.clone(),
+        settings: tg.profile.clone(),
+        nodes: graph.raw_nodes().iter().map(|n| utils::GraphNode { pos: n.weight.pos, stoparea: n.weight.stoparea,
+            etapp: n.weight.etapp.as_ref().map(|e| e.0.clone()), km: n.weight.etapp.as_ref().map(|e| e.1).unwrap_or(0f64) }).collect(),
+        edges: graph.raw_edges().iter().map(|e| utils::GraphEdge { a: e.source().index(), b: e.target().index(),
+            dist: e.weight.dist, geom: e.weight.geom.clone(), ascent: e.weight.ascent, descent: e.weight.descent,
+            time_ab: e.weight.time_fwd, time_ba: e.weight.time_back, detour: e.weight.detour,
+            surface: e.weight.surface.clone(), difficulty: e.weight.difficulty, features: e.weight.features.iter().cloned().collect() }).collect(),
+        variants: tg.variants.clone(),
+        closures: tg.closures.clone(),
+    }
+}
+
+// Where each A/B variant etapp leaves and joins the main trail: the closest
+// main trail point within link of its first and last points.
+fn find_variants(graph: &Graph<Node, Edge>, link: f64) -> Vec<utils::Variant> {
+    let main_index = utils::spatial::Grid::from_iter(500f64, graph.node_indices()
+        .filter(|&ni| graph[ni].etapp.is_some() && utils::variant_of(graph[ni].etapp_name()).is_none())
+        .filter(|&ni| graph.neighbors_undirected(ni).count() > 0)
+        .map(|ni| (graph[ni].pos, ni)));
+    let km = |ni: NodeIndex| graph[ni].etapp.as_ref().unwrap().1;
+    let mut ends: BTreeMap<&str, (NodeIndex, NodeIndex)> = BTreeMap::new();
+    for ni in graph.node_indices().filter(|&ni| graph[ni].etapp.is_some()) {
+        let name = graph[ni].etapp_name();
+        if utils::variant_of(name).is_none() { continue; }
+        let e = ends.entry(name).or_insert((ni, ni));
+        if km(ni) < km(e.0) { e.0 = ni; }
+        if km(ni) > km(e.1) { e.1 = ni; }
+    }
+    let meet = |ni: NodeIndex| match main_index.closest(graph[ni].pos, |_| true) {
+        Some((nn, d)) if d <= link => Some((graph[nn].etapp_name().to_string(), km(nn))),
+        _ => None,
+    };
+    ends.into_iter().map(|(name, (first, last))| utils::Variant { etapp: name.into(),
+        main: utils::variant_of(name).unwrap().into(), leaves: meet(first), joins: meet(last) }).collect()
+}
+
+fn add_node(g: &mut Graph<Node, Edge>, pos: (f64, f64), etapp: &str, d: f64) -> NodeIndex {
+    g.add_node(Node { pos: pos, stoparea: None, etapp: Some((etapp.into(), d)) })
+}
+
+fn add_node2(g: &mut Graph<Node, Edge>, pos: (f64, f64), sa: i32) -> NodeIndex {
+    g.add_node(Node { pos: pos, stoparea: Some(sa), etapp: None })
+}
+
+/// The etapper and the stop areas with their access walks, as GeoJSON for network.geojson.
+pub fn network_geojson(graph: &Graph<Node, Edge>, etapper: &BTreeMap<String, Vec<(f64, f64)>>, stopareas: &BTreeMap<i32, StopArea>) -> String {
+    use rustc_serialize::json::{Json, ToJson};
+    use utils::geojson;
+    let mut features = vec!();
+    for (k, v) in etapper {
+        let mut props = geojson::Properties::new();
+        props.insert("etapp".into(), k.to_json());
+        props.insert("led".into(), utils::led_name(k).to_json());
+        let length = v.windows(2).map(|w| dist(w[0], w[1])).fold(0f64, |a, b| a + b);
+        props.insert("km".into(), (length / 1000f64).to_json());
+        features.push(geojson::feature(geojson::line_string(v), props));
+    }
+    for ni in graph.node_indices().filter(|&ni| graph[ni].stoparea.is_some()) {
+        let sa = &stopareas[&graph[ni].stoparea.unwrap()];
+        let access: Vec<Json> = graph.edges(ni).map(|(nn, e)| {
+            let mut a = std::collections::BTreeMap::new();
+            a.insert("etapp".to_string(), graph[nn].etapp_name().to_json());
+            a.insert("km".to_string(), (graph[nn].etapp.as_ref().unwrap().1 / 1000f64).to_json());
+            a.insert("walk".to_string(), (e.dist as i32).to_json());
+            Json::Object(a)
+        }).collect();
+        let mut props = geojson::Properties::new();
+        props.insert("id".into(), sa.id.to_json());
+        props.insert("name".into(), sa.name.to_json());
+        props.insert("access".into(), Json::Array(access));
+        features.push(geojson::feature(geojson::point(graph[ni].pos), props));
+    }
+    geojson::feature_collection(features).to_string()
+}
+
+#[derive(RustcEncodable)]
+struct AllPaths {
+    profile: String,
+    paths: Vec<Vec<i32>>,
+}
+
+/// Everything makeroutegraph writes, as (file name, contents) in the order written.
+/// The map is also written as net.html if html is set.
+pub fn outputs(tg: &TrailGraph, etapper: &BTreeMap<String, Vec<(f64, f64)>>, stopareas: &BTreeMap<i32, StopArea>,
+    paths: Vec<utils::Path>, no_stop_dist: f64, html: bool) -> Vec<(&'static str, String)> {
+    use rustc_serialize::json::encode;
+    let (svg, page) = render::make_map(&tg.graph, stopareas, &tg.profile_name, html);
+    let (diag_txt, diag_json) = diag::diagnose(&tg.graph, etapper, &tg.profile_name, no_stop_dist);
+    let all_paths = AllPaths { profile: tg.profile_name.clone(), paths: paths.iter().map(|p| vec!(p.dist, p.src, p.dest)).collect() };
+    let v = utils::PathsFile { profile: tg.profile_name.clone(), settings: tg.profile.clone(), paths: paths };
+    let mut r = vec!(("../fetchkoords/data/net.svg", svg));
+    if let Some(page) = page { r.push(("../fetchkoords/data/net.html", page)); }
+    r.extend(vec!(("../data/network.geojson", network_geojson(&tg.graph, etapper, stopareas)),
+        ("../data/diagnostics.txt", diag_txt),
+        ("../data/diagnostics.json", diag_json),
+        ("../data/graph.json", encode(&tg.graph_file()).unwrap()),
+        ("../fetchkoords/data/all_paths.json", encode(&all_paths).unwrap()),
+        ("../data/paths.json", encode(&v).unwrap())));
+    r
+}
+
+fn do_stop_area_work(graph: &mut Graph<Node, Edge>, stopareas: &BTreeMap<i32, StopArea>, p: &utils::Profile, roads: Option<&osm::RoadNet>) {
+    let area_to_ni: BTreeMap<i32, NodeIndex> = stopareas.values()
+        .filter(|v| !p.exclude_stops.iter().any(|e| v.name.find(&**e).is_some()))
+        .map(|v| (v.id, add_node2(graph, (v.x as f64, v.y as f64), v.id))).collect();
+    println!("Added {} stop areas", area_to_ni.len());
+
+    // For every stop area, link to each distinct trail section within reach.
+    // Points along the same etapp closer than access_spacing to an already
+    // linked point are the same access point, so only the closest is kept.
+    let trail_index = utils::spatial::Grid::from_iter(500f64, graph.node_indices()
+        .filter(|&ni| graph[ni].etapp.is_some() && graph.neighbors_undirected(ni).count() > 0)
+        .map(|ni| (graph[ni].pos, ni)));
+    let mut candidates = 0;
+    for &sa_ni in area_to_ni.values() {
+        let mut links: Vec<(NodeIndex, f64)> = vec!();
+        for (ni, d) in trail_index.within(graph[sa_ni].pos, p.stop_attach) {
+            let (ref e, km) = *graph[ni].etapp.as_ref().unwrap();
+            if links.iter().any(|&(nj, _)| {
+                let (ref e2, km2) = *graph[nj].etapp.as_ref().unwrap();
+                e == e2 && (km - km2).abs() < p.access_spacing
+            }) { continue; }
+            links.push((ni, d));
+        }
+        if links.len() > 0 { candidates += 1; }
+        let sa_name = &stopareas[&graph[sa_ni].stoparea.unwrap()].name;
+
+        // Replace straight lines with walks along roads and footways, where we know them.
+        let walks = match roads {
+            Some(r) => {
+                let targets: Vec<_> = links.iter().map(|&(ni, _)| graph[ni].pos).collect();
+                r.walk(graph[sa_ni].pos, &targets, p.stop_attach * p.road_detour)
+            }
+            None => links.iter().map(|_| None).collect(),
+        };
+        for ((ni, d), walk) in links.into_iter().zip(walks) {
+            let e = match walk {
+                Some((wd, geom)) => Edge { geom: geom, .. Edge::new(wd) },
+                None => {
+                    if roads.is_some() { println!("No road from {} to {}, using straight line", sa_name, graph[ni].etapp_name()); }
+                    Edge::new(d)
+                }
+            };
+            println!("Connecting {} with {} at km {:.1} ({} m, {} m walk)", sa_name, graph[ni].etapp_name(),
+                graph[ni].etapp.as_ref().unwrap().1 / 1000f64, d as i32, e.dist as i32);
+            graph.add_edge(sa_ni, ni, e);
+        }
+    }
+    println!("{} stop areas are candidates", candidates);
+}
+
+/// Reads a JSON file into T, None if there is no such file.
+pub fn read_json<T: rustc_serialize::Decodable>(fname: &str) -> Option<T> {
+    use std::io::Read;
+    let mut f = match std::fs::File::open(fname) { Ok(f) => f, Err(_) => return None };
+    let mut s = String::new();
+    f.read_to_string(&mut s).unwrap();
+    Some(rustc_serialize::json::decode(&s).unwrap())
+}
+
+// The two neighbours of ni, if ni is just a point passed through along an etapp:
+// no junction, other etapp or stop area is attached to it.
+fn through_node(graph: &Graph<Node, Edge>, ni: NodeIndex) -> Option<(NodeIndex, NodeIndex)> {
+    if graph[ni].etapp.is_none() { return None; }
+    let z: Vec<_> = graph.neighbors_undirected(ni).collect();
+    if z.len() != 2 || z[0] == z[1] { return None; }
+    for &nn in &z {
+        if graph[nn].etapp.is_none() || graph[nn].etapp_name() != graph[ni].etapp_name() { return None; }
+        let (e, _) = graph.find_edge_undirected(ni, nn).unwrap();
+        if graph[e].km.is_none() { return None; }
+    }
+    // Would give two edges between the same nodes, around a closed etapp
+    if graph.find_edge_undirected(z[0], z[1]).is_some() { return None; }
+    Some((z[0], z[1]))
+}
+
+/// The graph of the etapper, as built by makeroutegraph for a profile.
+pub struct TrailGraph {
+    pub profile_name: String,
+    pub profile: utils::Profile,
+    pub graph: Graph<Node, Edge>,
+    pub variants: Vec<utils::Variant>,
+    pub closures: Vec<utils::Closure>,
+}
+
+impl TrailGraph {
+    /// Builds the graph of the etapper, given as RT90 points per etapp name.
+    /// Crossings get junctions, loose etapp ends are linked to nearby etapper and
+    /// etapper sharing a corridor are merged. Elevation per point, if known, is used
+    /// for walking times. A/B variants are matched with the main trail they replace.
+    pub fn from_etapper(profile_name: &str, profile: &utils::Profile, etapper: &BTreeMap<String, Vec<(f64, f64)>>,
+        elevation: Option<&BTreeMap<String, Vec<Option<f64>>>>) -> TrailGraph {
+        let mut graph = petgraph::Graph::new();
+
+        for (k, v) in etapper {
+            let ele = elevation.and_then(|e| e.get(k));
+            let mut prevn = None;
+            let mut d = 0f64;
+            for (i, &p) in v.iter().enumerate() {
+                let newn = add_node(&mut graph, p, &k, d);
+                prevn.map(|pn| {
+                    let dd = dist({ let dummy: &Node = &graph[pn]; dummy.pos }, p);
+                    d += dd;
+                    let rise = match ele.map(|e| (e.get(i - 1), e.get(i))) {
+                        Some((Some(&Some(e1)), Some(&Some(e2)))) => e2 - e1,
+                        _ => 0f64,
+                    };
+                    if dd < profile.max_edge {
+                        graph.add_edge(pn, newn, Edge { km: Some((d - dd, d)), .. Edge::climb(dd, rise, &profile.cost_model) });
+                    }
+                });
+                prevn = Some(newn);
+            }
+        }
+
+        println!("Graph has {} nodes", graph.node_count());
+        let crossings = junctions::add_crossings(&mut graph, profile.max_edge);
+        println!("Added junctions at {} crossings", crossings);
+
+        let trail_index = utils::spatial::Grid::from_iter(500f64, graph.node_indices().map(|ni| (graph[ni].pos, ni)));
+        let endpoints: Vec<_> = graph.node_indices().filter(|&ni| graph.neighbors_undirected(ni).count() < 2).collect();
+        for ni in endpoints {
+            if graph.neighbors_undirected(ni).count() >= 2 { continue; }
+            let (ng, d) = {
+                let gg: &Node = &graph[ni];
+                let (ng, d) = match trail_index.closest(gg.pos, |nn| graph[nn].etapp_name() != gg.etapp_name()) {
+                    Some(x) => x, None => continue };
+                if d > profile.endpoint_link {
+                    println!("{} is not close to anything, at least {} m", gg.etapp_name(), d as i32);
+                    continue;
+                }
+                println!("add link between {} and {} ({} m)", gg.etapp_name(), graph[ng].etapp_name(), d as i32);
+                (ng, d)
+            };
+            graph.add_edge(ni, ng, Edge::new(d));
+        }
+
+        let merged = junctions::merge_corridors(&mut graph, profile.corridor_width, profile.max_edge);
+        println!("Removed {} edges in shared corridors", merged);
+
+        let variants = find_variants(&graph, profile.endpoint_link);
+        for v in &variants {
+            let at = |x: &Option<(String, f64)>| x.as_ref().map(|x| format!("{} km {:.1}", x.0, x.1 / 1000f64)).unwrap_or("nowhere".into());
+            println!("{} is a variant of {}, from {} to {}", v.etapp, v.main, at(&v.leaves), at(&v.joins));
+        }
+        TrailGraph { profile_name: profile_name.into(), profile: profile.clone(), graph: graph, variants: variants, closures: vec!() }
+    }
+
+    /// Links the stop areas to the trail within reach, along roads if known.
+    pub fn attach_stop_areas(&mut self, stopareas: &BTreeMap<i32, StopArea>, roads: Option<&osm::RoadNet>) {
+        do_stop_area_work(&mut self.graph, stopareas, &self.profile, roads);
+    }
+
+    /// Adds the closures, with a detour edge between the ends of the closed stretch
+    /// for those that have one. Detours are only walked while their closure is active.
+    pub fn add_closures(&mut self, closures: &[utils::Closure]) {
+        for c in closures {
+            let i = self.closures.len();
+            self.closures.push(c.clone());
+            if c.detour.len() == 0 { continue; }
+            let (a, b) = {
+                // The detour starts and ends on trail still open, the last point before the closed stretch and the first after it.
+                let graph = &self.graph;
+                let km = |ni: NodeIndex| graph[ni].etapp.as_ref().unwrap().1;
+                let on_etapp: Vec<NodeIndex> = graph.node_indices()
+                    .filter(|&ni| graph[ni].etapp.as_ref().map(|e| e.0 == c.etapp).unwrap_or(false))
+                    .filter(|&ni| graph.neighbors_undirected(ni).count() > 0).collect();
+                let (lo, hi) = (c.from_km.min(c.to_km), c.from_km.max(c.to_km));
+                let before = on_etapp.iter().cloned().filter(|&ni| km(ni) <= lo).max_by_key(|&ni| km(ni) as i64);
+                let after = on_etapp.iter().cloned().filter(|&ni| km(ni) >= hi).min_by_key(|&ni| km(ni) as i64);
+                match (before, after) {
+                    (Some(a), Some(b)) if a != b => (a, b),
+                    _ => { println!("Closure of {} km {:.1} - {:.1} is not on the trail", c.etapp, c.from_km / 1000f64, c.to_km / 1000f64); continue }
+                }
+            };
+            let graph = &mut self.graph;
+            // The detour may be given in either direction.
+            let (a, b) = if dist(graph[a].pos, c.detour[0]) <= dist(graph[b].pos, c.detour[0]) { (a, b) } else { (b, a) };
+            let mut geom = vec!(graph[a].pos);
+            geom.extend(c.detour.iter().cloned());
+            geom.push(graph[b].pos);
+            let d = geom.windows(2).map(|w| dist(w[0], w[1])).fold(0f64, |x, y| x + y);
+            println!("Detour of {:.1} km around {} km {:.1} - {:.1}", d / 1000f64, c.etapp, c.from_km / 1000f64, c.to_km / 1000f64);
+            graph.add_edge(a, b, Edge { geom: geom, detour: Some(i), .. Edge::new(d) });
+        }
+    }
+
+    /// Sets surface, difficulty and features on the trail edges along each stretch.
+    /// Where stretches overlap, surface is counted for each and the hardest difficulty wins.
+    pub fn add_attributes(&mut self, stretches: &[config::Stretch]) {
+        let edges: Vec<(usize, String)> = self.graph.raw_edges().iter().enumerate()
+            .filter(|&(_, e)| e.weight.km.is_some() && e.weight.detour.is_none())
+            .map(|(i, e)| (i, self.graph[e.source()].etapp_name().to_string())).collect();
+        let mut n = 0;
+        for (i, name) in edges {
+            let e = &mut self.graph[EdgeIndex::new(i)];
+            let (k1, k2) = e.km.unwrap();
+            if k1 == k2 { continue; }
+            let mut found = false;
+            for st in stretches.iter().filter(|st| st.etapp == name) {
+                // Stretches are given in km, the whole etapp if left out
+                let lo = st.from_km.map(|k| k * 1000f64).unwrap_or(0f64);
+                let hi = st.to_km.map(|k| k * 1000f64).unwrap_or(std::f64::INFINITY);
+                let overlap = hi.min(k1.max(k2)) - lo.max(k1.min(k2));
+                if overlap <= 0f64 { continue; }
+                let part = overlap / (k2 - k1).abs();
+                if let Some(ref s) = st.surface { *e.surface.entry(s.clone()).or_insert(0f64) += part * e.dist; }
+                e.difficulty = std::cmp::max(e.difficulty, st.difficulty.unwrap_or(0));
+                if let Some(ref f) = st.features { e.features.extend(f.iter().cloned()); }
+                found = true;
+            }
+            if found { n += 1; }
+        }
+        println!("Added surface and difficulty to {} edges", n);
+    }
+
+    /// Removes the nodes just passed through along an etapp, joining their two edges into one
+    /// that keeps the walked geometry and km range. Stop areas without links are removed too.
+    pub fn contract(&mut self) {
+        let graph = &mut self.graph;
+        let (nodes, edges) = (graph.node_count(), graph.edge_count());
+        // Each merge changes the neighbours of the next, so every node is checked when its turn comes.
+        let candidates: Vec<NodeIndex> = graph.node_indices().collect();
+        for ni in candidates {
+            let (a, b) = match through_node(graph, ni) { Some(x) => x, None => continue };
+            let e = walk_edge(graph, a, ni).join(walk_edge(graph, ni, b));
+            for &nn in &[a, b] {
+                let (old, _) = graph.find_edge_undirected(ni, nn).unwrap();
+                graph.remove_edge(old);
+            }
+            graph.add_edge(a, b, e);
+        }
+
+        // Nodes merged away and stop areas out of reach have no edges left
+        graph.retain_nodes(|g, ni| g.neighbors_undirected(ni).count() >= 1);
+        println!("Contracted graph from {} nodes and {} edges to {} nodes and {} edges",
+            nodes, edges, graph.node_count(), graph.edge_count());
+    }
+
+    pub fn graph_file(&self) -> utils::GraphFile {
+        make_graph_file(self)
+    }
+
+    /// The suggested paths between the stop areas, ordered by stop areas and then alternatives.
+    /// The searches from the stop areas are split over the given number of threads.
+    pub fn paths_between(&self, stopareas: &BTreeMap<i32, StopArea>, threads: usize) -> Vec<utils::Path> {
+        let router = Arc::new(utils::route::Router::new(self.graph_file()));
+        find_paths(router, &self.profile, stopareas, threads)
+    }
+}
+
+// Is path a better than b: less walking on the trail, then less walking to and from it.
+fn better(a: &utils::Path, b: &utils::Path) -> bool {
+    let key = |p: &utils::Path| (p.traildist, p.srcdist + p.destdist);
+    key(a) < key(b)
+}
+
+// Suggested paths, ordered by stop areas and then alternatives. Every pair of stop areas is
+// searched from both ends; the search giving the better best path wins, earlier ones on ties.
+fn find_paths(router: Arc<utils::route::Router>, profile: &utils::Profile, stopareas: &BTreeMap<i32, StopArea>, threads: usize) -> Vec<utils::Path> {
+    let q = utils::route::Query { min_trail: profile.min_path, max_trail: profile.max_path,
+        alternatives: profile.alternatives, .. Default::default() };
+    let stops = router.stop_nodes();
+    let threads = std::cmp::max(threads, 1);
+    // Thread t searches from every stop area i with i % threads == t.
+    let handles: Vec<_> = (0..threads).map(|t| {
+        let (router, q, stops) = (router.clone(), q.clone(), stops.clone());
+        std::thread::spawn(move || {
+            stops.iter().enumerate().filter(|&(i, _)| i % threads == t).map(|(i, &ni)| {
+                (i, router.paths_from(ni, &q).into_iter().chain(router.loops_from(ni, &q)).collect::<Vec<_>>())
+            }).collect::<Vec<_>>()
+        })
+    }).collect();
+    let mut searches: Vec<(usize, Vec<utils::Path>)> = handles.into_iter().flat_map(|h| h.join().unwrap()).collect();
+    searches.sort_by(|a, b| a.0.cmp(&b.0));
+
+    // Results are merged in the order of the stop areas, as if searched one after another.
+    let mut pairs: BTreeMap<(i32, i32), Vec<utils::Path>> = BTreeMap::new();
+    for (i, v) in searches {
+        let sid = router.graph.nodes[stops[i]].stoparea.unwrap();
+        println!("Found {} paths from {}", v.len(), stopareas[&sid].name);
+        let mut found: BTreeMap<i32, Vec<utils::Path>> = BTreeMap::new();
+        for mut path in v {
+            if path.dest < path.src { path.reverse(); }
+            let other = if path.src == sid { path.dest } else { path.src };
+            found.entry(other).or_insert(vec!()).push(path);
+        }
+        for (_, mut v) in found {
+            v.sort_by(|a, b| a.alternative.cmp(&b.alternative));
+            let key = (v[0].src, v[0].dest);
+            if pairs.get(&key).map(|old| better(&v[0], &old[0])).unwrap_or(true) { pairs.insert(key, v); }
+        }
+    }
+
+    let mut paths = vec!();
+    for (_, v) in pairs {
+        for path in v {
+            println!("{} m ({} m) {}{}, visiting {}", path.dist, path.srcdist + path.destdist,
+                if path.src == path.dest { format!("around {}", stopareas[&path.src].name) }
+                else { format!("between {} and {}", stopareas[&path.src].name, stopareas[&path.dest].name) },
+                if path.alternative > 0 { format!(" (alternative {})", path.alternative) } else { "".into() },
+                path.etapper.iter().map(|v| if utils::variant_of(&v.etapp).is_some() { format!("{} (variant)", v.etapp) } else { v.etapp.clone() })
+                    .collect::<Vec<_>>().join(", "));
+            paths.push(path);
+        }
+    }
+    paths
+}
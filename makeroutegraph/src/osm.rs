@@ -0,0 +1,121 @@
+use std::collections::{HashMap, HashSet, BinaryHeap};
+use std::cmp::Ordering;
+use osmpbfreader::{OsmPbfReader, OsmObj, Tags};
+use utils::spatial::Grid;
+
+fn dist(a: (f64, f64), b: (f64, f64)) -> f64 { ((a.0 - b.0) * (a.0 - b.0) + (a.1 - b.1) * (a.1 - b.1)).sqrt() }
+
+fn walkable(tags: &Tags) -> bool {
+    let hw = match tags.get("highway") { Some(h) => h, None => return false };
+    let foot = tags.get("foot").map(|f| &**f);
+    if foot == Some("no") { return false; }
+    let foot_ok = foot == Some("yes") || foot == Some("designated") || foot == Some("permissive");
+    if !foot_ok && tags.get("access").map(|a| a == "no" || a == "private").unwrap_or(false) { return false; }
+    match &**hw {
+        "motorway" | "motorway_link" | "trunk" | "trunk_link" => foot_ok,
+        "construction" | "proposed" | "raceway" | "bus_guideway" | "platform" => false,
+        _ => true,
+    }
+}
+
+// Reversed on distance, so that BinaryHeap pops the closest node first.
+#[derive(PartialEq)]
+struct State(f64, usize);
+
+impl Eq for State {}
+
+impl PartialOrd for State {
+    fn partial_cmp(&self, rhs: &State) -> Option<Ordering> { Some(self.cmp(rhs)) }
+}
+
+impl Ord for State {
+    fn cmp(&self, rhs: &State) -> Ordering { rhs.0.partial_cmp(&self.0).unwrap_or(Ordering::Equal) }
+}
+
+/// Footways and roads from an OpenStreetMap extract, in RT90 coordinates.
+pub struct RoadNet {
+    pos: Vec<(f64, f64)>,
+    adj: Vec<Vec<(usize, f64)>>,
+    index: Grid<usize>,
+}
+
+impl RoadNet {
+    pub fn load(fname: &str) -> Option<RoadNet> {
+        let f = match ::std::fs::File::open(fname) { Ok(f) => f, Err(_) => return None };
+        println!("Reading roads from {}", fname);
+
+        // Ways come after nodes in the file, so read it twice: first ways, then the nodes they use.
+        let mut ways = vec!();
+        let mut needed = HashSet::new();
+        for obj in OsmPbfReader::new(f).iter() {
+            if let OsmObj::Way(w) = obj {
+                if !walkable(&w.tags) { continue; }
+                needed.extend(w.nodes.iter().cloned());
+                ways.push(w.nodes);
+            }
+        }
+
+        let f = ::std::fs::File::open(fname).unwrap();
+        let mut ids = HashMap::new();
+        let mut pos = vec!();
+        for obj in OsmPbfReader::new(f).iter() {
+            if let OsmObj::Node(n) = obj {
+                if !needed.contains(&n.id) { continue; }
+                ids.insert(n.id, pos.len());
+                pos.push(::utils::geo::wgs84_to_rt90(n.lat, n.lon));
+            }
+        }
+
+        let mut adj = vec![vec!(); pos.len()];
+        for w in ways {
+            for pair in w.windows(2) {
+                if let (Some(&a), Some(&b)) = (ids.get(&pair[0]), ids.get(&pair[1])) {
+                    let d = dist(pos[a], pos[b]);
+                    adj[a].push((b, d));
+                    adj[b].push((a, d));
+                }
+            }
+        }
+        let index = Grid::from_iter(200f64, pos.iter().cloned().zip(0..pos.len()));
+        println!("Road network has {} nodes", pos.len());
+        Some(RoadNet { pos: pos, adj: adj, index: index })
+    }
+
+    /// Walking distance and geometry from one point to each of the targets,
+    /// or None for targets that cannot be reached within max_dist.
+    /// The geometry starts at from and ends at the target.
+    pub fn walk(&self, from: (f64, f64), to: &[(f64, f64)], max_dist: f64) -> Vec<Option<(f64, Vec<(f64, f64)>)>> {
+        let (start, sd) = match self.index.closest(from, |_| true) { Some(x) => x, None => return to.iter().map(|_| None).collect() };
+
+        let mut best: HashMap<usize, (f64, usize)> = HashMap::new();
+        let mut heap = BinaryHeap::new();
+        best.insert(start, (sd, start));
+        heap.push(State(sd, start));
+        while let Some(State(d, n)) = heap.pop() {
+            if d > best[&n].0 { continue; }
+            for &(nn, dd) in &self.adj[n] {
+                let nd = d + dd;
+                if nd > max_dist { continue; }
+                if best.get(&nn).map(|&(od, _)| od <= nd).unwrap_or(false) { continue; }
+                best.insert(nn, (nd, n));
+                heap.push(State(nd, nn));
+            }
+        }
+
+        to.iter().map(|&p| {
+            let (tn, td) = match self.index.closest(p, |_| true) { Some(x) => x, None => return None };
+            let d = match best.get(&tn) { Some(&(d, _)) => d + td, None => return None };
+            if d > max_dist { return None; }
+            let mut geom = vec!(p);
+            let mut n = tn;
+            loop {
+                geom.push(self.pos[n]);
+                if n == start { break; }
+                n = best[&n].1;
+            }
+            geom.push(from);
+            geom.reverse();
+            Some((d, geom))
+        }).collect()
+    }
+}
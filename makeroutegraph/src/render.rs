@@ -0,0 +1,136 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use petgraph::Graph;
+use utils::xml_escape;
+use {Node, Edge, StopArea};
+
+const LED_COLOURS: [(&'static str, &'static str); 5] =
+    [("1", "#d7191c"), ("2", "#2b83ba"), ("3", "#1a9641"), ("4", "#e66101"), ("5", "#7b3294")];
+const ACCESS_COLOUR: &'static str = "#808080";
+
+fn colour(etapp: &str) -> &'static str {
+    let led = etapp.split("_").next().unwrap();
+    LED_COLOURS.iter().find(|c| c.0 == led).map(|c| c.1).unwrap_or("#000000")
+}
+
+// RT90 x grows northwards and y eastwards, SVG y grows downwards.
+struct Frame {
+    maxx: f64,
+    miny: f64,
+    scale: f64, // Pixels per meter
+    margin: f64,
+}
+
+impl Frame {
+    fn pt(&self, p: (f64, f64)) -> (f64, f64) {
+        ((p.1 - self.miny) * self.scale + self.margin, (self.maxx - p.0) * self.scale + self.margin)
+    }
+}
+
+fn polyline(fr: &Frame, ps: &[(f64, f64)]) -> String {
+    let mut s = String::new();
+    for &p in ps {
+        let (x, y) = fr.pt(p);
+        write!(s, "{:.1},{:.1} ", x, y).unwrap();
+    }
+    s
+}
+
+/// Draws the network north up, with a colour per led, stop areas, etapp
+/// labels, a scale bar and a legend. Returns the contents of net.svg, and if html is set,
+/// also of net.html which shows details of what is under the mouse.
+pub fn make_map(graph: &Graph<Node, Edge>, stopareas: &BTreeMap<i32, StopArea>, profile: &str, html: bool) -> (String, Option<String>) {
+    let scale = 0.01f64;
+    let margin = 40f64;
+    // The edges are drawn with their geometry, which may bend far from the nodes left after contraction.
+    let points = || graph.raw_nodes().iter().map(|m| m.weight.pos)
+        .chain(graph.raw_edges().iter().flat_map(|e| e.weight.geom.iter().cloned()));
+    let minx = points().map(|p| p.0 as i32).min().unwrap() as f64;
+    let maxx = points().map(|p| p.0 as i32).max().unwrap() as f64;
+    let miny = points().map(|p| p.1 as i32).min().unwrap() as f64;
+    let maxy = points().map(|p| p.1 as i32).max().unwrap() as f64;
+    let fr = Frame { maxx: maxx, miny: miny, scale: scale, margin: margin };
+    let (width, height) = ((maxy - miny) * scale + 2f64 * margin, (maxx - minx) * scale + 2f64 * margin);
+    println!("Map of ({},{}) to ({},{}) is {} x {} px", minx, miny, maxx, maxy, width as i32, height as i32);
+
+    let mut body = String::new();
+
+    // Trail edges, links between etapper, and access walks
+    for e in graph.raw_edges() {
+        let (a, b) = (&graph[e.source()], &graph[e.target()]);
+        let geom = if e.weight.geom.len() > 0 { e.weight.geom.clone() } else { vec!(a.pos, b.pos) };
+        let (col, w, info) = match (&a.etapp, &b.etapp) {
+            (&Some(ref x), &Some(ref y)) if x.0 == y.0 => (colour(&x.0), 2,
+                format!("{} etapp {}, km {:.1} - {:.1}", ::utils::led_name(&x.0), x.0, x.1 / 1000f64, y.1 / 1000f64)),
+            (&Some(ref x), &Some(ref y)) => ("#000000", 1, format!("Övergång {} - {}", x.0, y.0)),
+            _ => (ACCESS_COLOUR, 1, format!("Gång till leden, {:.1} km", e.weight.dist / 1000f64)),
+        };
+        let dash = if a.stoparea.is_some() || b.stoparea.is_some() { " stroke-dasharray=\"3,2\"" } else { "" };
+        write!(body, "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\"{} data-info=\"{}\"><title>{}</title></polyline>\n",
+            polyline(&fr, &geom), col, w, dash, xml_escape(&info), xml_escape(&info)).unwrap();
+    }
+
+    // Etapp labels, halfway along each etapp
+    let mut halfway: BTreeMap<&str, (f64, (f64, f64))> = BTreeMap::new();
+    let mut lengths: BTreeMap<&str, f64> = BTreeMap::new();
+    for n in graph.raw_nodes().iter().filter_map(|n| n.weight.etapp.as_ref()) {
+        let l = lengths.entry(&n.0[..]).or_insert(0f64);
+        if n.1 > *l { *l = n.1; }
+    }
+    for n in graph.raw_nodes().iter().map(|n| &n.weight).filter(|n| n.etapp.is_some()) {
+        let (ref name, km) = *n.etapp.as_ref().unwrap();
+        let off = (km - lengths[&**name] / 2f64).abs();
+        let h = halfway.entry(&name[..]).or_insert((off, n.pos));
+        if off < h.0 { *h = (off, n.pos); }
+    }
+    for (name, (_, p)) in halfway {
+        let (x, y) = fr.pt(p);
+        write!(body, "<text x=\"{:.1}\" y=\"{:.1}\" font-size=\"9\" fill=\"{}\" stroke=\"#ffffff\" stroke-width=\"0.3\">{}</text>\n",
+            x + 3f64, y - 3f64, colour(name), name).unwrap();
+    }
+
+    // Stop areas
+    for n in graph.raw_nodes().iter().map(|n| &n.weight).filter(|n| n.stoparea.is_some()) {
+        let sa = &stopareas[&n.stoparea.unwrap()];
+        let (x, y) = fr.pt(n.pos);
+        let info = format!("{} ({})", sa.name, sa.id);
+        write!(body, "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"2.5\" fill=\"#ffffff\" stroke=\"#000000\" data-info=\"{}\"><title>{}</title></circle>\n",
+            x, y, xml_escape(&info), xml_escape(&info)).unwrap();
+        write!(body, "<text x=\"{:.1}\" y=\"{:.1}\" font-size=\"6\" fill=\"#404040\">{}</text>\n", x + 3f64, y + 2f64, xml_escape(&sa.name)).unwrap();
+    }
+
+    // Scale bar, 10 km with a tick every 2 km
+    let (sx, sy) = (margin, height - margin / 2f64);
+    write!(body, "<g stroke=\"#000000\" stroke-width=\"1\">").unwrap();
+    write!(body, "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\"/>", sx, sy, sx + 10000f64 * scale, sy).unwrap();
+    for i in 0..6 {
+        let tx = sx + (i as f64) * 2000f64 * scale;
+        write!(body, "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\"/>", tx, sy - 4f64, tx, sy).unwrap();
+    }
+    write!(body, "</g><text x=\"{:.1}\" y=\"{:.1}\" font-size=\"9\">10 km</text>\n", sx + 10000f64 * scale + 5f64, sy + 3f64).unwrap();
+
+    // Legend and north arrow
+    let mut ly = margin;
+    for &(led, col) in LED_COLOURS.iter() {
+        write!(body, "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"{}\" stroke-width=\"3\"/>", margin, ly, margin + 20f64, ly, col).unwrap();
+        write!(body, "<text x=\"{:.1}\" y=\"{:.1}\" font-size=\"10\">{}</text>\n", margin + 25f64, ly + 3f64, ::utils::led_name(led)).unwrap();
+        ly += 14f64;
+    }
+    write!(body, "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"{}\" stroke-width=\"1\" stroke-dasharray=\"3,2\"/>",
+        margin, ly, margin + 20f64, ly, ACCESS_COLOUR).unwrap();
+    write!(body, "<text x=\"{:.1}\" y=\"{:.1}\" font-size=\"10\">Gång till hållplats</text>\n", margin + 25f64, ly + 3f64).unwrap();
+    let nx = width - margin;
+    write!(body, "<path d=\"M {:.1} {:.1} l -5 12 l 10 0 z\" fill=\"#000000\"/><text x=\"{:.1}\" y=\"{:.1}\" font-size=\"10\" text-anchor=\"middle\">N</text>\n",
+        nx, margin, nx, margin + 24f64).unwrap();
+
+    let svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{:.0}\" height=\"{:.0}\" viewBox=\"0 0 {:.0} {:.0}\" font-family=\"sans-serif\">\n<!-- profile: {} -->\n<rect width=\"100%\" height=\"100%\" fill=\"#ffffff\"/>\n{}</svg>\n",
+        width, height, width, height, xml_escape(profile), body);
+
+    if !html { return (svg, None); }
+    let mut f = String::new();
+    write!(f, "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Skåneleden</title>\n").unwrap();
+    write!(f, "<style>#info {{ position: fixed; top: 10px; right: 10px; background: #ffffe0; border: 1px solid #808080; padding: 4px; font-family: sans-serif; }} [data-info]:hover {{ stroke-width: 4; }}</style>\n").unwrap();
+    write!(f, "</head><body>\n<div id=\"info\">Profil: {}</div>\n{}", xml_escape(profile), svg).unwrap();
+    write!(f, "<script>\nvar info = document.getElementById('info');\ndocument.querySelectorAll('[data-info]').forEach(function(e) {{\n  e.addEventListener('mouseover', function() {{ info.textContent = e.getAttribute('data-info'); }});\n}});\n</script>\n</body></html>\n").unwrap();
+    (svg, Some(f))
+}
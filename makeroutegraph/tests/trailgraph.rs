@@ -0,0 +1,354 @@
+extern crate makeroutegraph;
+extern crate utils;
+extern crate petgraph;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use petgraph::graph::NodeIndex;
+use makeroutegraph::{TrailGraph, StopArea, config};
+use utils::Path;
+use utils::route::{Router, Query, walk_time};
+
+// n points every 500 m from start, northwards (x) if north, else eastwards (y).
+fn line(start: (f64, f64), north: bool, n: usize) -> Vec<(f64, f64)> {
+    (0..n).map(|i| if north { (start.0 + 500f64 * i as f64, start.1) } else { (start.0, start.1 + 500f64 * i as f64) }).collect()
+}
+
+fn stopareas(v: &[(i32, i32, i32)]) -> BTreeMap<i32, StopArea> {
+    v.iter().map(|&(id, x, y)| (id, StopArea { id: id, name: format!("Hållplats {}", id), x: x, y: y })).collect()
+}
+
+fn etapp_of(tg: &TrailGraph, ni: NodeIndex) -> Option<&str> { tg.graph[ni].etapp.as_ref().map(|e| &*e.0) }
+
+// Edges between two different etapper, as (etapp, etapp, dist).
+fn links(tg: &TrailGraph) -> Vec<(String, String, f64)> {
+    tg.graph.raw_edges().iter().filter_map(|e| match (etapp_of(tg, e.source()), etapp_of(tg, e.target())) {
+        (Some(a), Some(b)) if a != b => Some((a.to_string(), b.to_string(), e.weight.dist)),
+        _ => None,
+    }).collect()
+}
+
+// Stop areas link to the closest trail point only.
+fn build(etapper: &BTreeMap<String, Vec<(f64, f64)>>, stops: &BTreeMap<i32, StopArea>) -> TrailGraph {
+    build_with(etapper, stops, Default::default(), &|_| {})
+}
+
+// As build, with closures, attributes etc added by setup before contraction.
+fn build_with(etapper: &BTreeMap<String, Vec<(f64, f64)>>, stops: &BTreeMap<i32, StopArea>, profile: utils::Profile,
+    setup: &Fn(&mut TrailGraph)) -> TrailGraph {
+    let profile = utils::Profile { stop_attach: 1000f64, .. profile };
+    let mut tg = TrailGraph::from_etapper("default", &profile, etapper, None);
+    tg.attach_stop_areas(stops, None);
+    setup(&mut tg);
+    tg.contract();
+    tg
+}
+
+// 1_1 running 10 km north, with stop area 1 at its start and 2 at its end.
+fn straight() -> (BTreeMap<String, Vec<(f64, f64)>>, BTreeMap<i32, StopArea>) {
+    let mut etapper = BTreeMap::new();
+    etapper.insert("1_1".to_string(), line((6150000f64, 1350000f64), true, 21));
+    (etapper, stopareas(&[(1, 6150000, 1350300), (2, 6160000, 1349800)]))
+}
+
+fn stop_node(router: &Router, id: i32) -> usize {
+    router.stop_nodes().into_iter().find(|&n| router.graph.nodes[n].stoparea == Some(id)).unwrap()
+}
+
+// The best path from stop area src to dest, if any.
+fn path_between(tg: &TrailGraph, src: i32, dest: i32, q: &Query) -> Option<Path> {
+    let router = Router::new(tg.graph_file());
+    let n = stop_node(&router, src);
+    router.paths_from(n, q).into_iter().find(|p| p.dest == dest && p.alternative == 0)
+}
+
+// Trail 40 km, otherwise the default query.
+fn query() -> Query { Query { max_trail: 40000f64, .. Default::default() } }
+
+#[test]
+fn crossing_gets_junction() {
+    let mut etapper = BTreeMap::new();
+    etapper.insert("1_1".to_string(), line((6150000f64, 1350000f64), true, 21));
+    etapper.insert("2_1".to_string(), line((6155250f64, 1345250f64), false, 21));
+    let tg = TrailGraph::from_etapper("default", &Default::default(), &etapper, None);
+    let l = links(&tg);
+    assert_eq!(l.len(), 1);
+    assert_eq!(l[0].2, 0f64);
+    let e = &tg.graph.raw_edges()[tg.graph.raw_edges().iter().position(|e| e.weight.dist == 0f64).unwrap()];
+    assert_eq!(tg.graph[e.source()].pos, (6155250f64, 1350000f64));
+    assert_eq!(tg.graph[e.target()].pos, (6155250f64, 1350000f64));
+}
+
+#[test]
+fn gap_breaks_etapp() {
+    let mut etapper = BTreeMap::new();
+    let mut v = line((6150000f64, 1350000f64), true, 11);
+    v.extend(line((6157000f64, 1350000f64), true, 7));
+    etapper.insert("1_1".to_string(), v);
+    // Starts 100 m from where 1_1 ends
+    etapper.insert("1_2".to_string(), line((6160100f64, 1350000f64), true, 11));
+    let tg = TrailGraph::from_etapper("default", &Default::default(), &etapper, None);
+    assert!(tg.graph.raw_edges().iter().all(|e| e.weight.dist < 1000f64));
+    let l = links(&tg);
+    assert_eq!(l.len(), 1);
+    assert!((l[0].2 - 100f64).abs() < 1e-6);
+}
+
+#[test]
+fn offset_corridor() {
+    let mut etapper = BTreeMap::new();
+    etapper.insert("1_1".to_string(), line((6150000f64, 1350000f64), true, 21));
+    // 15 m east of 1_1 from km 2.25 to km 6.25, with its points between those of 1_1, then off to the east
+    let mut v = line((6152250f64, 1350015f64), true, 9);
+    v.extend(line((6156250f64, 1350515f64), false, 3));
+    etapper.insert("2_1".to_string(), v);
+    let tg = TrailGraph::from_etapper("default", &Default::default(), &etapper, None);
+    let l = links(&tg);
+    assert_eq!(l.len(), 1);
+    assert_eq!((&*l[0].0, &*l[0].1), ("2_1", "1_1"));
+    assert!((l[0].2 - 15f64).abs() < 1e-6);
+    // Only the part of 2_1 off to the east is left
+    let along: Vec<_> = tg.graph.raw_edges().iter().filter(|e| e.weight.km.is_some() && etapp_of(&tg, e.source()) == Some("2_1")).collect();
+    assert_eq!(along.len(), 3);
+}
+
+#[test]
+fn stop_attachment() {
+    let mut etapper = BTreeMap::new();
+    etapper.insert("1_1".to_string(), line((6150000f64, 1350000f64), true, 21));
+    let stops = stopareas(&[(1, 6150000, 1350300), (2, 6160000, 1349800), (3, 6100000, 1300000)]);
+    let tg = build(&etapper, &stops);
+    let stop_node = |id| tg.graph.node_indices().find(|&ni| tg.graph[ni].stoparea == Some(id));
+    assert!(stop_node(3).is_none());
+    for &(id, walk, km) in &[(1, 300f64, 0f64), (2, 200f64, 10000f64)] {
+        let ni = stop_node(id).unwrap();
+        let (nn, e) = tg.graph.edges(ni).min_by_key(|&(_, e)| e.dist as i32).unwrap();
+        assert!((e.dist - walk).abs() < 1e-6);
+        assert_eq!(tg.graph[nn].etapp.as_ref().unwrap().1, km);
+    }
+    let paths = tg.paths_between(&stops, 3);
+    let p = paths.iter().find(|p| p.src == 1 && p.dest == 2 && p.alternative == 0).unwrap();
+    assert_eq!((p.traildist, p.srcdist, p.destdist, p.dist), (10000, 300, 200, 10500));
+}
+
+#[test]
+fn deterministic() {
+    let mut etapper = BTreeMap::new();
+    etapper.insert("1_1".to_string(), line((6150000f64, 1350000f64), true, 21));
+    etapper.insert("1_2".to_string(), line((6160000f64, 1350000f64), true, 21));
+    etapper.insert("2_1".to_string(), line((6155250f64, 1345250f64), false, 21));
+    let stops = stopareas(&[(1, 6150000, 1350300), (2, 6158000, 1349700), (3, 6166000, 1350200),
+        (4, 6155200, 1345000), (5, 6154800, 1354500)]);
+    // Every file written, byte for byte
+    let run = || {
+        let tg = build(&etapper, &stops);
+        let paths = tg.paths_between(&stops, 3);
+        assert!(paths.len() > 0);
+        makeroutegraph::outputs(&tg, &etapper, &stops, paths, 5000f64, true)
+    };
+    let (a, b) = (run(), run());
+    assert_eq!(a.len(), 8);
+    for (x, y) in a.iter().zip(b.iter()) { assert_eq!(x, y); }
+}
+
+#[test]
+fn access_limits() {
+    // 1_1 runs 10 km north, 1 km east and 5 km back south. Stop area 2 is between the two
+    // long legs, 900 m from km 7 and 100 m from km 14.
+    let mut v = line((6150000f64, 1350000f64), true, 21);
+    v.extend(line((6160000f64, 1350500f64), false, 2));
+    v.extend((1..11).map(|i| (6160000f64 - 500f64 * i as f64, 1351000f64)));
+    let mut etapper = BTreeMap::new();
+    etapper.insert("1_1".to_string(), v);
+    let tg = build(&etapper, &stopareas(&[(1, 6150000, 1350300), (2, 6157000, 1350900)]));
+    let walk = |q: Query| path_between(&tg, 1, 2, &q).map(|p| (p.traildist, p.destdist, p.dist));
+    assert_eq!(walk(query()), Some((7000, 900, 8200)));
+    assert_eq!(walk(Query { max_access: Some(500f64), .. query() }), Some((14000, 100, 14400)));
+    assert_eq!(walk(Query { max_access: Some(200f64), .. query() }), None);
+    assert_eq!(walk(Query { max_dist: Some(8200f64), .. query() }), Some((7000, 900, 8200)));
+    assert_eq!(walk(Query { max_dist: Some(8100f64), .. query() }), None);
+}
+
+#[test]
+fn contraction_keeps_geometry() {
+    let mut etapper = BTreeMap::new();
+    etapper.insert("1_1".to_string(), line((6150000f64, 1350000f64), true, 21));
+    etapper.insert("1_2".to_string(), line((6160000f64, 1350000f64), true, 11));
+    let stops = stopareas(&[(1, 6150000, 1350300), (2, 6155000, 1349800)]);
+    let tg = build(&etapper, &stops);
+    // Stop areas, their access points, both ends where 1_1 meets 1_2, and the end of 1_2
+    assert_eq!(tg.graph.node_count(), 7);
+    let trail: Vec<_> = tg.graph.raw_edges().iter().filter(|e| e.weight.km.is_some()).collect();
+    assert_eq!(trail.len(), 3);
+    for e in trail {
+        let (a, b) = (&tg.graph[e.source()], &tg.graph[e.target()]);
+        assert_eq!(a.etapp_name(), b.etapp_name());
+        let (ka, kb) = e.weight.km.unwrap();
+        assert_eq!((ka, kb), (a.etapp.as_ref().unwrap().1, b.etapp.as_ref().unwrap().1));
+        assert_eq!(e.weight.dist, (kb - ka).abs());
+        assert_eq!(e.weight.geom.len() as f64, (kb - ka).abs() / 500f64 + 1f64);
+        assert_eq!((e.weight.geom[0], *e.weight.geom.last().unwrap()), (a.pos, b.pos));
+    }
+}
+
+#[test]
+fn variant_replaces_main() {
+    let mut etapper = BTreeMap::new();
+    etapper.insert("1_1".to_string(), line((6150000f64, 1350000f64), true, 21));
+    // Runs alongside 1_1 from km 2 to km 6, 200 m east of it
+    etapper.insert("1_1A".to_string(), line((6152000f64, 1350200f64), true, 9));
+    let tg = TrailGraph::from_etapper("default", &Default::default(), &etapper, None);
+    assert_eq!(tg.variants.len(), 1);
+    let v = &tg.variants[0];
+    assert_eq!((&*v.etapp, &*v.main), ("1_1A", "1_1"));
+    assert_eq!(v.leaves, Some(("1_1".to_string(), 2000f64)));
+    assert_eq!(v.joins, Some(("1_1".to_string(), 6000f64)));
+    assert_eq!(v.replaces(), vec!(("1_1".to_string(), 2000f64, 6000f64)));
+
+    // Digitised against the trail, from 1_2 back to 1_1
+    let v = utils::Variant { etapp: "1_1A".into(), main: "1_1".into(), leaves: Some(("1_2".into(), 1000f64)), joins: Some(("1_1".into(), 9000f64)) };
+    assert_eq!(v.replaces(), vec!(("1_1".to_string(), 9000f64, ::std::f64::INFINITY), ("1_2".to_string(), 0f64, 1000f64)));
+}
+
+#[test]
+fn variant_preference() {
+    let (mut etapper, stops) = straight();
+    etapper.insert("1_1A".to_string(), line((6152000f64, 1350200f64), true, 9));
+    let tg = build(&etapper, &stops);
+    let walk = |variants: &str| {
+        let p = path_between(&tg, 1, 2, &Query { variants: variants.into(), .. query() }).unwrap();
+        (p.traildist, p.etapper.iter().any(|v| v.etapp == "1_1A"))
+    };
+    assert_eq!(walk("either"), (10000, false));
+    assert_eq!(walk("main"), (10000, false));
+    assert_eq!(walk("variants"), (10400, true));
+}
+
+#[test]
+fn climbing_times() {
+    // 1_1 climbs 25 m every 500 m, 500 m in all, from stop area 1 to 2. The walks to the trail are flat, 500 m together.
+    let (etapper, stops) = straight();
+    let mut elevation = BTreeMap::new();
+    elevation.insert("1_1".to_string(), (0..21).map(|i| Some(25f64 * i as f64)).collect::<Vec<_>>());
+    let times = |model: &str| {
+        let profile = utils::Profile { cost_model: model.into(), stop_attach: 1000f64, .. Default::default() };
+        let mut tg = TrailGraph::from_etapper("default", &profile, &etapper, Some(&elevation));
+        tg.attach_stop_areas(&stops, None);
+        tg.contract();
+        let p = path_between(&tg, 1, 2, &query()).unwrap();
+        assert_eq!((p.ascent, p.descent), (500, 0));
+        (p.time, p.time_back)
+    };
+    let expect = |model: &str, rise: f64| (walk_time(model, 10000f64, rise) + walk_time(model, 500f64, 0f64)).round() as i32;
+    assert_eq!(times("distance"), (7560, 7560));
+    let (up, down) = times("tobler");
+    assert!(up > 7560 && down < 7560);
+    assert!((up - expect("tobler", 500f64)).abs() <= 1 && (down - expect("tobler", -500f64)).abs() <= 1);
+    assert_eq!(times("naismith"), (7560 + 3000, 7560));
+}
+
+#[test]
+fn alternatives() {
+    // 1_1A leaves 1_1 at km 2 and joins it at km 6, so the way over it shares about 60% of its trail with 1_1.
+    let (mut etapper, stops) = straight();
+    etapper.insert("1_1A".to_string(), line((6152000f64, 1350200f64), true, 9));
+    let walks = |alt_overlap: f64| {
+        let tg = build_with(&etapper, &stops, utils::Profile { alt_overlap: alt_overlap, .. Default::default() }, &|_| {});
+        let router = Router::new(tg.graph_file());
+        let n = stop_node(&router, 1);
+        router.paths_from(n, &Query { alternatives: 3, .. query() }).into_iter().filter(|p| p.dest == 2)
+            .map(|p| (p.alternative, p.traildist, p.etapper.iter().any(|v| v.etapp == "1_1A"))).collect::<Vec<_>>()
+    };
+    // With the main trail more expensive, the second search takes the variant. The third finds the main trail again.
+    assert_eq!(walks(0.7), vec!((0, 10000, false), (1, 10400, true)));
+    assert_eq!(walks(0.5), vec!((0, 10000, false)));
+}
+
+// Circular paths from stop area src.
+fn loops_at(tg: &TrailGraph, src: i32, q: &Query) -> Vec<Path> {
+    let router = Router::new(tg.graph_file());
+    let n = stop_node(&router, src);
+    router.loops_from(n, q)
+}
+
+// Loops walking all of their trail twice are kept too.
+fn any_loop() -> utils::Profile { utils::Profile { loop_reuse: 1f64, .. Default::default() } }
+
+#[test]
+fn loop_avoids_etapp() {
+    let (mut etapper, stops) = straight();
+    // Runs alongside 1_1 from km 2 to km 6, 200 m east of it
+    etapper.insert("1_1A".to_string(), line((6152000f64, 1350200f64), true, 9));
+    let tg = build_with(&etapper, &stops, any_loop(), &|_| {});
+    let loops = loops_at(&tg, 1, &query());
+    assert!(loops.iter().any(|p| p.etapper.iter().any(|v| v.etapp == "1_1A")));
+    let mut avoid = HashSet::new();
+    avoid.insert("1_1A".to_string());
+    let loops = loops_at(&tg, 1, &Query { avoid: avoid, .. query() });
+    assert!(loops.iter().all(|p| p.etapper.iter().all(|v| v.etapp != "1_1A")));
+}
+
+#[test]
+fn closure_detour() {
+    let (etapper, stops) = straight();
+    let tg = build_with(&etapper, &stops, Default::default(), &|tg| tg.add_closures(&[utils::Closure { etapp: "1_1".into(),
+        from_km: 4000f64, to_km: 6000f64, from_date: "2026-09-01".into(), to_date: "2026-11-30".into(), reason: "Avverkning".into(),
+        detour: vec!((6154000f64, 1351000f64), (6156000f64, 1351000f64)) }]));
+    let walk = |date: Option<&str>| {
+        let p = path_between(&tg, 1, 2, &Query { date: date.map(|d| d.to_string()), .. query() }).unwrap();
+        (p.traildist, p.detours)
+    };
+    assert_eq!(walk(None), (10000, vec!()));
+    assert_eq!(walk(Some("2026-08-31")), (10000, vec!()));
+    assert_eq!(walk(Some("2026-10-01")), (12000, vec!(0)));
+}
+
+#[test]
+fn closure_between_points() {
+    let (etapper, stops) = straight();
+    // Track points are every 500 m, the detour starts at km 4 and ends at km 6.
+    let tg = build_with(&etapper, &stops, Default::default(), &|tg| tg.add_closures(&[utils::Closure { etapp: "1_1".into(),
+        from_km: 5600f64, to_km: 4400f64, from_date: "2026-09-01".into(), to_date: "2026-11-30".into(), reason: "Avverkning".into(),
+        detour: vec!((6154000f64, 1351000f64), (6156000f64, 1351000f64)) }]));
+    let p = path_between(&tg, 1, 2, &Query { date: Some("2026-10-01".into()), .. query() }).unwrap();
+    assert_eq!(p.traildist, 12000);
+}
+
+#[test]
+fn loop_around_closure() {
+    let (etapper, stops) = straight();
+    let tg = build_with(&etapper, &stops, any_loop(), &|tg| tg.add_closures(&[utils::Closure { etapp: "1_1".into(),
+        from_km: 4000f64, to_km: 6000f64, from_date: "2026-09-01".into(), to_date: "2026-11-30".into(), reason: "Avverkning".into(),
+        detour: vec!((6154000f64, 1351000f64), (6156000f64, 1351000f64)) }]));
+    // Without a date the detour is never walked, and during the closure the closed stretch is not,
+    // so there is nothing to walk around either way.
+    assert_eq!(loops_at(&tg, 1, &query()).len(), 0);
+    assert_eq!(loops_at(&tg, 1, &Query { date: Some("2026-10-01".into()), .. query() }).len(), 0);
+}
+
+#[test]
+fn difficulty_detour() {
+    let (mut etapper, stops) = straight();
+    etapper.insert("1_1A".to_string(), line((6152000f64, 1350200f64), true, 9));
+    let tg = build_with(&etapper, &stops, Default::default(), &|tg| tg.add_attributes(&[config::Stretch { etapp: "1_1".into(),
+        from_km: Some(3f64), to_km: Some(4f64), surface: None, difficulty: Some(3), features: None }]));
+    assert_eq!(path_between(&tg, 1, 2, &query()).map(|p| (p.traildist, p.difficulty)), Some((10000, 3)));
+    // Around the hard stretch along 1_1A, 200 m east of 1_1
+    assert_eq!(path_between(&tg, 1, 2, &Query { max_difficulty: Some(2), .. query() }).map(|p| (p.traildist, p.difficulty)), Some((10400, 0)));
+}
+
+#[test]
+fn stretch_attributes() {
+    let (etapper, stops) = straight();
+    let tg = build_with(&etapper, &stops, Default::default(), &|tg| tg.add_attributes(&[config::Stretch { etapp: "1_1".into(),
+        from_km: Some(2f64), to_km: Some(4f64), surface: Some("asphalt".into()), difficulty: Some(2), features: Some(vec!("beach".into())) }]));
+    let p = path_between(&tg, 1, 2, &query()).unwrap();
+    assert_eq!(p.surface.get("asphalt"), Some(&2000));
+    assert_eq!(p.difficulty, 2);
+    assert_eq!(p.features, vec!("beach".to_string()));
+
+    assert_eq!(path_between(&tg, 1, 2, &Query { max_difficulty: Some(1), .. query() }).map(|p| p.difficulty), None);
+
+    let mut max_surface = HashMap::new();
+    max_surface.insert("asphalt".to_string(), 0.1f64);
+    assert!(path_between(&tg, 1, 2, &Query { max_surface: max_surface, .. query() }).is_none());
+}
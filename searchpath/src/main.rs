@@ -4,6 +4,8 @@ extern crate chrono;
 extern crate hyper;
 extern crate xml;
 
+mod multiday;
+
 use std::collections::{HashMap, HashSet};
 
 type TimeStamp = chrono::NaiveDateTime; // chrono::DateTime<chrono::FixedOffset>;
@@ -17,8 +19,16 @@ struct StopArea {
 }
 
 struct SearchParams {
-    min_distance: i32,
+    min_distance: i32, // On the trail, not counting walks to and from it
     max_distance: i32,
+    max_total: Option<i32>, // Including walks to and from the trail
+    max_access: Option<i32>, // Longest walk between a stop area and the trail
+    avoid: HashSet<String>, // Etapper not to walk on
+    alternatives: usize, // Max number of paths between two stop areas
+    variants: String, // "main", "variants" or "either", see utils::route::Query
+    max_surface: HashMap<String, f64>, // Most of the trail on each surface, from 0 to 1
+    max_difficulty: Option<i32>,
+    features: Vec<String>, // Each of these somewhere along the trail
 
     walk_speed: i32, // meters per hour
 
@@ -27,6 +37,16 @@ struct SearchParams {
     dest_sa: StopArea,
 }
 
+impl SearchParams {
+    fn query(&self) -> utils::route::Query {
+        utils::route::Query { min_trail: self.min_distance as f64, max_trail: self.max_distance as f64,
+            max_dist: self.max_total.map(|m| m as f64), max_access: self.max_access.map(|m| m as f64),
+            avoid: self.avoid.clone(), alternatives: self.alternatives, variants: self.variants.clone(),
+            date: Some(date_str(self.origin_time)), max_surface: self.max_surface.clone(),
+            max_difficulty: self.max_difficulty, features: self.features.clone() }
+    }
+}
+
 
 fn ask_stop_area(n: &str) -> Option<StopArea> {
     let mut url = hyper::Url::parse("http://www.labs.skanetrafiken.se/v2.2/querystation.asp").unwrap();
@@ -147,31 +167,69 @@ fn ask_journeys(from: &StopArea, to: &StopArea, deptime: TimeStamp) -> Vec<Journ
     journeys
 }
 
-fn fix_etapp(s: &str) -> String {
-    let mut ss = HashMap::new();
-    for i in s.split(";").filter(|i| i.len() > 0) {
-        let v: Vec<_> = i.split("_").collect();
-        let mut e = ss.entry(v[0]).or_insert(vec!());
-        e.push(v[1]);
-    }
-    let mut r = "".into();
-    for (k, mut v) in ss {
-       v.sort();
-       let led = match k {
-            "1" => "Kust-kustleden",
-            "2" => "Nord-sydleden",
-            "3" => "Ås-åsleden",
-            "4" => "Österlenleden",
-            "5" => "Öresundsleden",
-            _ => panic!("Unknown led {}", v[0]),
+// E g "Nord-sydleden etapp 4 (km 3.2) → etapp 5 → etapp 6 (km 7.8)", in the order they are walked.
+fn fix_etapp(v: &[utils::EtappVisit]) -> String {
+    let mut r: Vec<String> = vec!();
+    let mut i = 0;
+    while i < v.len() {
+        let led = v[i].etapp.split("_").next().unwrap();
+        let mut j = i;
+        while j + 1 < v.len() && v[j + 1].etapp.split("_").next().unwrap() == led { j += 1; }
+        let num = |e: &utils::EtappVisit| {
+            let n = e.etapp.splitn(2, "_").nth(1).unwrap_or("").to_string();
+            if utils::variant_of(&e.etapp).is_some() { format!("{} (alternativ sträckning)", n) } else { n }
         };
-        r = format!("{}{} etapp {}", if r == "" { r } else { format!("{}, ", r) }, led, v.join(", "));
+        if i == j {
+            r.push(format!("{} etapp {} (km {:.1} → {:.1})", utils::led_name(led), num(&v[i]), v[i].from_km / 1000f64, v[i].to_km / 1000f64));
+        } else {
+            let mut s = format!("{} etapp {} (km {:.1})", utils::led_name(led), num(&v[i]), v[i].from_km / 1000f64);
+            for e in &v[i + 1..j] { s = format!("{} → etapp {}", s, num(e)); }
+            r.push(format!("{} → etapp {} (km {:.1})", s, num(&v[j]), v[j].to_km / 1000f64));
+        }
+        i = j + 1;
     }
-    r
+    r.join(", sedan ")
 }
 
 fn to_km(i: i32) -> f64 { (i as f64)/1000f64 }
 
+// E g "60% path, 30% gravel, 10% asphalt, medel, beach", empty if nothing is known.
+fn fix_attributes(path: &utils::Path) -> String {
+    let mut r: Vec<String> = path.surface.iter().filter(|&(_, &d)| path.traildist > 0 && d > 0)
+        .map(|(s, &d)| (s, d * 100 / path.traildist)).map(|(s, pct)| format!("{}% {}", pct, s)).collect();
+    match path.difficulty { 1 => r.push("lätt".into()), 2 => r.push("medel".into()), 3 => r.push("krävande".into()), _ => {} }
+    r.extend(path.features.iter().cloned());
+    r.join(", ")
+}
+
+fn date_str(t: TimeStamp) -> String { t.format("%Y-%m-%d").to_string() }
+
+// Warnings for the closures on date that the path walks up to, or uses the detour of.
+fn closure_notes(path: &utils::Path, closures: &[utils::Closure], date: &str) -> Vec<String> {
+    closures.iter().enumerate().filter(|&(i, c)| c.active(date) &&
+        (path.detours.contains(&i) || path.etapper.iter().any(|v| c.touches(&v.etapp, v.from_km, v.to_km)))).map(|(_, c)| {
+        format!("Obs! {} km {:.1} - {:.1} är avstängd till {}{}{}", c.etapp, c.from_km / 1000f64, c.to_km / 1000f64, c.to_date,
+            if c.reason.len() > 0 { format!(" ({})", c.reason) } else { "".into() },
+            if c.detour.len() > 0 { ", följ omvägen" } else { "" })
+    }).collect()
+}
+
+// E g "5_1A går i stället för Öresundsleden etapp 1 km 2.3 → 5.1", for every A/B variant walked.
+fn variant_notes(path: &utils::Path, variants: &[utils::Variant]) -> Vec<String> {
+    path.etapper.iter().filter_map(|e| variants.iter().find(|v| v.etapp == e.etapp)).map(|v| {
+        let main = |x: &(String, f64)| format!("{} etapp {} km {:.1}", utils::led_name(&x.0), x.0.splitn(2, "_").nth(1).unwrap_or(""), x.1 / 1000f64);
+        match (&v.leaves, &v.joins) {
+            (&Some(ref a), &Some(ref b)) => format!("{} går i stället för {} → {}", v.etapp, main(a), main(b)),
+            _ => format!("{} går i stället för en del av etapp {}", v.etapp, v.main),
+        }
+    }).collect()
+}
+
+// The path's estimated walking time, scaled from utils::route::FLAT_SPEED to walk_speed.
+fn walk_duration(path: &utils::Path, walk_speed: i32) -> chrono::Duration {
+    chrono::Duration::seconds(((path.time as f64) * utils::route::FLAT_SPEED / (walk_speed as f64)) as i64)
+}
+
 fn time_to_str(d: TimeStamp, reftime: TimeStamp) -> String {
     if d.date() == reftime.date() { d.format("%H:%M").to_string() }
     else { d.format("%Y-%m-%d %H:%M").to_string() }
@@ -184,18 +242,38 @@ struct FullPath {
     score: i32,
 }
 
-fn do_search(p: &SearchParams, paths: &Vec<utils::Path>, stopareas: &HashMap<i32, StopArea>) {
-
-    // Add paths for both directions.
-    let mut paths2 = vec!();
-    for v in paths.iter() {
-        if v.dist < p.min_distance { continue };
-        if v.dist > p.max_distance { continue };
-        paths2.push(v.clone());
-        let mut vv = v.clone();
-        vv.reverse();
-        paths2.push(vv);
+// A suggestion as written to GPX and GeoJSON, walked over one or more days.
+struct Trip {
+    origj: Journey,
+    destj: Journey,
+    days: Vec<utils::Path>,
+    sleep: Vec<String>, // Where each night is spent
+}
+
+impl Trip {
+    fn src<'a>(&self, stopareas: &'a HashMap<i32, StopArea>) -> &'a StopArea { &stopareas[&self.days[0].src] }
+    fn dest<'a>(&self, stopareas: &'a HashMap<i32, StopArea>) -> &'a StopArea { &stopareas[&self.days.last().unwrap().dest] }
+
+    // E g "Från Höör till Sjöbo", "Runt Höör" or for trips over several days "Dag 2, från Vindskydd Hallamölla till Sjöbo".
+    fn title(&self, day: usize, stopareas: &HashMap<i32, StopArea>) -> String {
+        let (src, dest) = (self.src(stopareas), self.dest(stopareas));
+        if self.days.len() == 1 {
+            return if src.id == dest.id { format!("Runt {}", src.name) } else { format!("Från {} till {}", src.name, dest.name) };
+        }
+        let from = if day == 0 { &src.name } else { &self.sleep[day - 1] };
+        let to = if day + 1 == self.days.len() { &dest.name } else { &self.sleep[day] };
+        format!("Dag {}, från {} till {}", day + 1, from, to)
     }
+}
+
+fn do_search(p: &SearchParams, router: &utils::route::Router, stopareas: &HashMap<i32, StopArea>) -> Vec<FullPath> {
+
+    // Search paths from every stop area, this gives paths in both directions.
+    let q = p.query();
+    // Circular paths start and end at the same stop area, so the journey home starts where the journey out ended.
+    let paths2: Vec<utils::Path> = router.stop_nodes().into_iter()
+        .flat_map(|n| router.paths_from(n, &q).into_iter().chain(router.loops_from(n, &q))).collect();
+    println!("{} paths between {} and {} m found", paths2.len(), p.min_distance, p.max_distance);
 
     // Search for origin journeys 
     let sa_origin_tocheck: HashSet<i32> = paths2.iter().map(|v| v.src).collect();
@@ -212,19 +290,25 @@ fn do_search(p: &SearchParams, paths: &Vec<utils::Path>, stopareas: &HashMap<i32
             |(&id, js)| Journey::best(js, p.origin_time).map(|(score, j)| (id, (score, j.clone())))
         ).collect();
 
-    // Search for destination journeys
-    let sa_dest_threads: Vec<(utils::Path, Journey, i32, _, _)> = paths2.iter()
-        .filter_map(|v| origin_scores.get(&v.src).map(|&(os, ref oj)| (v, os, oj.clone())))
-        .map(|(v, os, oj)| {
-            let sa1 = stopareas[&v.dest].clone();
-            let sa2 = p.dest_sa.clone();
-            let time = oj.arrtime + chrono::Duration::seconds((v.dist as i64) * 3600i64 / (p.walk_speed as i64));
-            let th = std::thread::spawn(move || { ask_journeys(&sa1, &sa2, time) });
-            (v.clone(), oj, os, time, th)
-        }).collect();
-    let mut full_paths: Vec<FullPath> = sa_dest_threads.into_iter().filter_map(|(v, origj, origs, destdeptime, th)| {
-        let destjs = th.join().unwrap();
-        let (dests, destj) = if let Some(j) = Journey::best(&destjs, destdeptime) { j } else { return None };
+    // Search for destination journeys, once for every pair of stop areas. Alternatives between the
+    // same stop areas share them, from when the quickest walk is done, and each picks the best
+    // journey after its own walk.
+    let walks: Vec<(utils::Path, Journey, i32, TimeStamp)> = paths2.iter()
+        .filter_map(|v| origin_scores.get(&v.src).map(|&(os, ref oj)| (v.clone(), oj.clone(), os, oj.arrtime + walk_duration(v, p.walk_speed))))
+        .collect();
+    let mut sa_dest_tocheck: HashMap<(i32, i32), TimeStamp> = HashMap::new();
+    for &(ref v, _, _, time) in &walks {
+        let t = sa_dest_tocheck.entry((v.src, v.dest)).or_insert(time);
+        if time < *t { *t = time; }
+    }
+    let sa_dest_threads: Vec<((i32, i32), _)> = sa_dest_tocheck.into_iter().map(|((src, dest), time)| {
+        let sa1 = stopareas[&dest].clone();
+        let sa2 = p.dest_sa.clone();
+        ((src, dest), std::thread::spawn(move || { ask_journeys(&sa1, &sa2, time) }))
+    }).collect();
+    let dest_journeys: HashMap<(i32, i32), Vec<Journey>> = sa_dest_threads.into_iter().map(|(k, th)| (k, th.join().unwrap())).collect();
+    let mut full_paths: Vec<FullPath> = walks.into_iter().filter_map(|(v, origj, origs, destdeptime)| {
+        let (dests, destj) = if let Some(j) = Journey::best(&dest_journeys[&(v.src, v.dest)], destdeptime) { j } else { return None };
         let totalscore = origs + dests - v.srcdist - v.destdist;
         Some(FullPath { origj: origj, destj: destj.clone(), path: v, score: totalscore })  
     }).collect();
@@ -232,6 +316,8 @@ fn do_search(p: &SearchParams, paths: &Vec<utils::Path>, stopareas: &HashMap<i32
     // Present result
     full_paths.sort_by(|v1, v2| v2.score.cmp(&v1.score));
     let mut sa_skip = HashSet::new();
+    let mut shown = vec!();
+    let alternatives: Vec<utils::Path> = full_paths.iter().map(|i| i.path.clone()).collect();
     for i in full_paths {
         if sa_skip.contains(&i.path.src) || sa_skip.contains(&i.path.dest) { continue; }
 
@@ -239,13 +325,23 @@ fn do_search(p: &SearchParams, paths: &Vec<utils::Path>, stopareas: &HashMap<i32
         let dest_name = &stopareas[&i.path.dest].name;
 
         println!("");
-        println!("Från {} till {}: minst {:.1} km", src_name, dest_name, to_km(i.path.dist));
+        let wd = walk_duration(&i.path, p.walk_speed);
+        let title = if i.path.src == i.path.dest { format!("Runt {}", src_name) } else { format!("Från {} till {}", src_name, dest_name) };
+        println!("{}. {}: minst {:.1} km, cirka {}:{:02} h, {} m uppför och {} m nedför", shown.len() + 1, title, to_km(i.path.dist),
+            wd.num_hours(), wd.num_minutes() % 60, i.path.ascent, i.path.descent);
         println!("  Res {}, från {} kl {} till {} kl {}, {} {}",
             i.origj.duration_as_string(), p.origin_sa.name, time_to_str(i.origj.deptime, p.origin_time),
             src_name, time_to_str(i.origj.arrtime, p.origin_time),
             i.origj.changes, if i.origj.changes == 1 {"byte"} else {"byten"});
         println!("  Gå minst {:.1} km, från {} till Skåneleden", to_km(i.path.srcdist), src_name);
-        println!("  Gå {:.1} km, på {}", to_km(i.path.dist - i.path.srcdist - i.path.destdist), fix_etapp(&i.path.etapp));
+        println!("  Gå {:.1} km, på {}", to_km(i.path.traildist), fix_etapp(&i.path.etapper));
+        let attrs = fix_attributes(&i.path);
+        if attrs.len() > 0 { println!("    {}", attrs); }
+        for n in variant_notes(&i.path, &router.graph.variants) { println!("    {}", n); }
+        for n in closure_notes(&i.path, &router.graph.closures, &date_str(p.origin_time)) { println!("    {}", n); }
+        for a in alternatives.iter().filter(|a| a.src == i.path.src && a.dest == i.path.dest && a.alternative != i.path.alternative) {
+            println!("    eller {:.1} km, på {}", to_km(a.traildist), fix_etapp(&a.etapper));
+        }
         println!("  Gå minst {:.1} km, från Skåneleden till {}", to_km(i.path.destdist), dest_name);
         println!("  Res {}, från {} kl {} till {} kl {}, {} {}",
             i.destj.duration_as_string(), dest_name, time_to_str(i.destj.deptime, p.origin_time), 
@@ -253,9 +349,118 @@ fn do_search(p: &SearchParams, paths: &Vec<utils::Path>, stopareas: &HashMap<i32
             i.destj.changes, if i.destj.changes == 1 {"byte"} else {"byten"});
         sa_skip.insert(i.path.src);
         sa_skip.insert(i.path.dest);
+        shown.push(i);
     }
+    shown
 }
 
+fn write_gpx(fname: &str, p: &SearchParams, t: &Trip, stopareas: &HashMap<i32, StopArea>) {
+    use std::io::Write;
+    use utils::geo::rt90_to_wgs84;
+    use utils::xml_escape;
+    let (src, dest) = (t.src(stopareas), t.dest(stopareas));
+    let mut f = std::fs::File::create(fname).unwrap();
+    write!(f, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n").unwrap();
+    write!(f, "<gpx version=\"1.1\" creator=\"searchpath\" xmlns=\"http://www.topografix.com/GPX/1/1\">\n").unwrap();
+
+    let mut wpts = vec!((sa_pos(src), &src.name, format!("Ankomst kl {} från {}", time_to_str(t.origj.arrtime, p.origin_time), p.origin_sa.name)));
+    for (i, name) in t.sleep.iter().enumerate() { wpts.push((*t.days[i].geom.last().unwrap(), name, format!("Natt {}", i + 1))); }
+    wpts.push((sa_pos(dest), &dest.name, format!("Avgång kl {} till {}", time_to_str(t.destj.deptime, p.origin_time), p.dest_sa.name)));
+    for &(pos, name, ref desc) in &wpts {
+        let (lat, lon) = rt90_to_wgs84(pos.0, pos.1);
+        write!(f, "<wpt lat=\"{:.6}\" lon=\"{:.6}\"><name>{}</name><desc>{}</desc></wpt>\n",
+            lat, lon, xml_escape(name), xml_escape(desc)).unwrap();
+    }
+
+    // A track per day. Walk to the trail, the trail itself, and walk from the trail, as separate segments.
+    for (day, path) in t.days.iter().enumerate() {
+        let g = &path.geom;
+        let (enter, leave) = match (path.marks.first(), path.marks.last()) {
+            (Some(a), Some(b)) => (a.index, b.index),
+            _ => (0, g.len() - 1),
+        };
+        write!(f, "<trk><name>{}</name>\n", xml_escape(&t.title(day, stopareas))).unwrap();
+        for seg in &[&g[..enter + 1], &g[enter..leave + 1], &g[leave..]] {
+            if seg.len() < 2 { continue; }
+            write!(f, "<trkseg>\n").unwrap();
+            for &(x, y) in seg.iter() {
+                let (lat, lon) = rt90_to_wgs84(x, y);
+                write!(f, "<trkpt lat=\"{:.6}\" lon=\"{:.6}\"/>\n", lat, lon).unwrap();
+            }
+            write!(f, "</trkseg>\n").unwrap();
+        }
+        write!(f, "</trk>\n").unwrap();
+    }
+    write!(f, "</gpx>\n").unwrap();
+}
+
+fn sa_pos(sa: &StopArea) -> (f64, f64) { (sa.x as f64, sa.y as f64) }
+
+fn write_geojson(fname: &str, p: &SearchParams, shown: &[Trip], stopareas: &HashMap<i32, StopArea>) {
+    use rustc_serialize::json::{ToJson, Json};
+    use std::collections::BTreeMap;
+    use utils::geojson;
+    let mut features = vec!();
+    for (idx, t) in shown.iter().enumerate() {
+        let (src, dest) = (t.src(stopareas), t.dest(stopareas));
+        for (day, path) in t.days.iter().enumerate() {
+            let mut props = geojson::Properties::new();
+            props.insert("suggestion".into(), (idx + 1).to_json());
+            props.insert("day".into(), (day + 1).to_json());
+            props.insert("from".into(), (if day == 0 { &src.name } else { &t.sleep[day - 1] }).to_json());
+            props.insert("to".into(), (if day + 1 == t.days.len() { &dest.name } else { &t.sleep[day] }).to_json());
+            props.insert("dist".into(), path.dist.to_json());
+            props.insert("srcdist".into(), path.srcdist.to_json());
+            props.insert("destdist".into(), path.destdist.to_json());
+            props.insert("traildist".into(), path.traildist.to_json());
+            props.insert("ascent".into(), path.ascent.to_json());
+            props.insert("descent".into(), path.descent.to_json());
+            props.insert("duration".into(), walk_duration(path, p.walk_speed).num_minutes().to_json());
+            props.insert("surface".into(), Json::Object(path.surface.iter().map(|(k, v)| (k.clone(), v.to_json())).collect()));
+            props.insert("difficulty".into(), path.difficulty.to_json());
+            props.insert("features".into(), path.features.to_json());
+            props.insert("alternative".into(), path.alternative.to_json());
+            props.insert("etapper".into(), path.etapper.iter().map(|v| {
+                let mut a = BTreeMap::new();
+                a.insert("etapp".to_string(), v.etapp.to_json());
+                a.insert("from_km".to_string(), (v.from_km / 1000f64).to_json());
+                a.insert("to_km".to_string(), (v.to_km / 1000f64).to_json());
+                Json::Object(a)
+            }).collect::<Vec<_>>().to_json());
+            features.push(geojson::feature(geojson::line_string(&path.geom), props));
+        }
+
+        for (night, name) in t.sleep.iter().enumerate() {
+            let mut props = geojson::Properties::new();
+            props.insert("suggestion".into(), (idx + 1).to_json());
+            props.insert("name".into(), name.to_json());
+            props.insert("night".into(), (night + 1).to_json());
+            features.push(geojson::feature(geojson::point(*t.days[night].geom.last().unwrap()), props));
+        }
+
+        for &(sa, j, arrive) in &[(src, &t.origj, true), (dest, &t.destj, false)] {
+            let mut props = geojson::Properties::new();
+            props.insert("suggestion".into(), (idx + 1).to_json());
+            props.insert("id".into(), sa.id.to_json());
+            props.insert("name".into(), sa.name.to_json());
+            let (t, other) = if arrive { (j.arrtime, &p.origin_sa.name) } else { (j.deptime, &p.dest_sa.name) };
+            props.insert((if arrive { "arrival" } else { "departure" }).into(), time_to_str(t, p.origin_time).to_json());
+            props.insert((if arrive { "from" } else { "to" }).into(), other.to_json());
+            props.insert("changes".into(), j.changes.to_json());
+            features.push(geojson::feature(geojson::point(sa_pos(sa)), props));
+        }
+    }
+    geojson::write(fname, features);
+}
+
+
+// Value of a --name=value option
+fn get_opt(opts: &[String], name: &str) -> Option<String> {
+    opts.iter().filter_map(|o| {
+        let mut s = o[2..].splitn(2, '=');
+        if s.next() == Some(name) { s.next().map(|v| v.to_string()) } else { None }
+    }).next()
+}
 
 fn main() {
     use std::io::Read;
@@ -263,10 +468,24 @@ fn main() {
     let mut s = String::new();
     f.read_to_string(&mut s).unwrap();
     let stopareas: HashMap<i32, StopArea> = rustc_serialize::json::decode(&s).unwrap();
-    let paths = utils::read_paths();
-    let args: Vec<_> = std::env::args().collect();
+    let mut router = utils::route::Router::new(utils::read_graph());
+    let (opts, args): (Vec<String>, Vec<String>) = std::env::args().partition(|a| a.starts_with("--"));
     if args.len() < 4 {
-        println!("Usage: searchpath distance(m) speed(m/h) origin(stoparea name) starttime(yyyy-mm-ddThh:nn)");
+        println!("Usage: searchpath [options] distance(m) speed(m/h) origin(stoparea name) starttime(yyyy-mm-ddThh:nn)");
+        println!("  --window=m      Accept trail distances this far from distance (default 100)");
+        println!("  --max-total=m   Walk at most this far in total, to and from the trail included");
+        println!("  --max-access=m  Walk at most this far between a stop area and the trail");
+        println!("  --avoid=a,b     Do not walk on these etapper, e g 2_3,2_4");
+        println!("  --alternatives=n  Show up to n different paths between the same stops (default from graph.json)");
+        println!("  --variants=v    Where there are A/B variants, prefer main, variants or either (default either)");
+        println!("  --max-surface=s:p,t:q  Skip paths with more than p percent of their trail on surface s, e g asphalt:20");
+        println!("  --max-difficulty=n  Walk around trail harder than n, from 1 (lätt) to 3 (krävande)");
+        println!("  --features=a,b  Only paths passing each of these somewhere along the trail, e g beach");
+        println!("  --geojson=file  Write the suggestions as GeoJSON");
+        println!("  --gpx=name      Write each suggestion as name-1.gpx, name-2.gpx etc");
+        println!("  --days=n        Walk distance(m) a day for n days, sleeping along the trail");
+        println!("  --overnight=a,b Kinds of places to sleep at, e g shelter,camping,hotel (default all)");
+        println!("  --overnight-file=file  Places to sleep at (default ../data/overnight.json)");
         return;
     }
     let d: i32 = args[1].parse().unwrap();
@@ -279,8 +498,44 @@ fn main() {
             TimeStamp::from_timestamp((chrono::Local::now().naive_local().timestamp() / 1000) * 1000, 0)
         });
 
-    let sp = SearchParams { min_distance: d - 100, max_distance: d + 100, walk_speed: speed,
+    let window: i32 = get_opt(&opts, "window").map(|w| w.parse().unwrap()).unwrap_or(100);
+    let avoid = get_opt(&opts, "avoid").map(|a| a.split(",").map(|e| e.to_string()).collect()).unwrap_or(HashSet::new());
+
+    let alternatives = get_opt(&opts, "alternatives").map(|a| a.parse().unwrap()).unwrap_or(router.graph.settings.alternatives);
+
+    let max_total = get_opt(&opts, "max-total").map(|m| m.parse().unwrap());
+    let max_access = get_opt(&opts, "max-access").map(|m| m.parse().unwrap());
+
+    let variants = get_opt(&opts, "variants").unwrap_or("either".into());
+    if variants != "main" && variants != "variants" && variants != "either" { panic!("--variants must be main, variants or either"); }
+
+    let max_surface = get_opt(&opts, "max-surface").map(|a| a.split(",").map(|e| {
+        let mut s = e.splitn(2, ':');
+        let kind = s.next().unwrap().to_string();
+        (kind, s.next().expect("--max-surface needs surface:percent").parse::<f64>().unwrap() / 100f64)
+    }).collect()).unwrap_or(HashMap::new());
+    let max_difficulty = get_opt(&opts, "max-difficulty").map(|m| m.parse().unwrap());
+    let features = get_opt(&opts, "features").map(|a| a.split(",").map(|e| e.to_string()).collect()).unwrap_or(vec!());
+
+    let sp = SearchParams { min_distance: d - window, max_distance: d + window, max_total: max_total, max_access: max_access, avoid: avoid,
+        variants: variants, max_surface: max_surface, max_difficulty: max_difficulty, features: features, alternatives: alternatives, walk_speed: speed,
         origin_sa: origin.clone(), dest_sa: origin, origin_time: otime };
 
-    do_search(&sp, &paths, &stopareas);
+    let days: usize = get_opt(&opts, "days").map(|a| a.parse().unwrap()).unwrap_or(1);
+    let shown = if days > 1 {
+        let kinds = get_opt(&opts, "overnight").map(|a| a.split(",").map(|e| e.to_string()).collect()).unwrap_or(HashSet::new());
+        let fname = get_opt(&opts, "overnight-file").unwrap_or("../data/overnight.json".into());
+        multiday::plan(&sp, days, &fname, &kinds, &mut router, &stopareas)
+    } else {
+        do_search(&sp, &router, &stopareas).into_iter().map(|i| Trip { origj: i.origj, destj: i.destj, days: vec!(i.path), sleep: vec!() }).collect()
+    };
+
+    if let Some(f) = get_opt(&opts, "geojson") { write_geojson(&f, &sp, &shown, &stopareas); }
+    if let Some(f) = get_opt(&opts, "gpx") {
+        for (idx, t) in shown.iter().enumerate() {
+            let fname = format!("{}-{}.gpx", f, idx + 1);
+            write_gpx(&fname, &sp, t, &stopareas);
+            println!("Wrote {} (från {} till {})", fname, t.src(&stopareas).name, t.dest(&stopareas).name);
+        }
+    }
 }
@@ -0,0 +1,221 @@
+// Trips over several days, sleeping at shelters, campsites or hotels along the trail.
+// Only the first and last day need public transport.
+//
+// The overnight places are read from a JSON file with a list of
+// { "name": "Vindskydd Hallamölla", "kind": "shelter", "lat": 55.57, "lon": 14.03 }.
+
+use std::collections::{HashMap, HashSet};
+use utils::route::{Router, Query};
+use utils::{Path, EtappVisit};
+use {SearchParams, StopArea, Journey, Trip, TimeStamp, ask_journeys, walk_duration, time_to_str, to_km, fix_etapp, variant_notes, closure_notes, date_str, fix_attributes};
+
+#[derive(RustcDecodable, Debug, Clone)]
+pub struct Overnight {
+    pub name: String,
+    pub kind: String, // E g "shelter", "camping" or "hotel"
+    pub lat: f64,
+    pub lon: f64,
+}
+
+pub fn read_overnight(fname: &str) -> Vec<Overnight> {
+    use std::io::Read;
+    let mut f = ::std::fs::File::open(fname).unwrap_or_else(|e|
+        panic!("Can't open {}: {}. Give the places to sleep at with --overnight-file.", fname, e));
+    let mut s = String::new();
+    f.read_to_string(&mut s).unwrap();
+    ::rustc_serialize::json::decode(&s).unwrap()
+}
+
+// Consecutive days, and the node where the last one ends.
+#[derive(Clone)]
+struct Chain {
+    days: Vec<Path>,
+    places: Vec<usize>, // Node where each day ends
+}
+
+// Meters of trail walked by both a and b.
+fn shared(a: &[EtappVisit], b: &[EtappVisit]) -> f64 {
+    let mut r = 0f64;
+    for x in a {
+        for y in b.iter().filter(|y| y.etapp == x.etapp) {
+            let lo = x.from_km.min(x.to_km).max(y.from_km.min(y.to_km));
+            let hi = x.from_km.max(x.to_km).min(y.from_km.max(y.to_km));
+            if hi > lo { r += hi - lo; }
+        }
+    }
+    r
+}
+
+// A new day may go on from the chain if it does not go back to an earlier place,
+// and does not walk too much of the trail walked on earlier days.
+fn fits(c: &Chain, dest: usize, p: &Path, max_reuse: f64) -> bool {
+    if c.places.contains(&dest) { return false; }
+    let reused = c.days.iter().map(|d| shared(&d.etapper, &p.etapper)).fold(0f64, |a, b| a + b);
+    reused <= max_reuse * p.traildist as f64
+}
+
+// Only the chains with the least walking to and from the trail are kept between days.
+const MAX_CHAINS: usize = 2000;
+
+// Every way to walk the given number of days: the first from a stop area to a place, then between
+// places, and the last from a place to a stop area. Legs are (node where they end, path) per node they start at.
+fn walk_days(first: Vec<(usize, Path)>, between: &HashMap<usize, Vec<(usize, Path)>>, last: &HashMap<usize, Vec<(usize, Path)>>,
+    days: usize, max_reuse: f64) -> Vec<Chain> {
+    let mut chains: Vec<Chain> = first.into_iter().map(|(n, path)| Chain { days: vec!(path), places: vec!(n) }).collect();
+    for day in 2..days + 1 {
+        let legs = if day == days { last } else { between };
+        let mut next = vec!();
+        for c in &chains {
+            for &(n, ref path) in legs.get(c.places.last().unwrap()).map(|v| &**v).unwrap_or(&[]) {
+                if !fits(c, n, path, max_reuse) { continue; }
+                let mut c2 = c.clone();
+                c2.days.push(path.clone());
+                c2.places.push(n);
+                next.push(c2);
+            }
+        }
+        next.sort_by(|a, b| {
+            let access = |c: &Chain| c.days.iter().map(|d| d.srcdist + d.destdist).fold(0, |x, y| x + y);
+            access(a).cmp(&access(b))
+        });
+        next.truncate(MAX_CHAINS);
+        chains = next;
+        println!("{} ways to walk {} days", chains.len(), day);
+    }
+    chains
+}
+
+/// Plans trips of the given number of days, each within the distance window of p, sleeping at the places
+/// in fname of the given kinds (all kinds if empty). Prints the best trips and returns them.
+pub fn plan(p: &SearchParams, days: usize, fname: &str, kinds: &HashSet<String>, router: &mut Router, stopareas: &HashMap<i32, StopArea>) -> Vec<Trip> {
+    let mut names = HashMap::new();
+    for o in read_overnight(fname).into_iter().filter(|o| kinds.len() == 0 || kinds.contains(&o.kind)) {
+        match router.add_place(::utils::geo::wgs84_to_rt90(o.lat, o.lon)) {
+            Some(n) => { names.insert(n, o); },
+            None => println!("{} is too far from the trail", o.name),
+        }
+    }
+    let places: Vec<usize> = names.keys().cloned().collect();
+    let stops = router.stop_nodes();
+    println!("{} places to sleep at", places.len());
+
+    // Legs are searched once for all days, so closures are avoided as on the first day and only warned about after that.
+    let q = Query { alternatives: 1, .. p.query() };
+    let max_reuse = router.graph.settings.day_reuse;
+    let first: Vec<Vec<(usize, Path)>> = stops.iter().map(|&s| router.paths_to(s, &places, &q)).collect();
+    let between: HashMap<usize, Vec<(usize, Path)>> = places.iter().map(|&n| (n, router.paths_to(n, &places, &q))).collect();
+    let last: HashMap<usize, Vec<(usize, Path)>> = places.iter().map(|&n| (n, router.paths_to(n, &stops, &q))).collect();
+
+    let chains = walk_days(first.into_iter().flat_map(|v| v.into_iter()).collect(), &between, &last, days, max_reuse);
+
+    // Public transport to the first day and from the last
+    let start_ids: HashSet<i32> = chains.iter().map(|c| c.days[0].src).collect();
+    let start_threads: Vec<(i32, _)> = start_ids.into_iter().map(|id| {
+        let (sa1, sa2, time) = (p.origin_sa.clone(), stopareas[&id].clone(), p.origin_time);
+        (id, ::std::thread::spawn(move || { ask_journeys(&sa1, &sa2, time) }))
+    }).collect();
+    let start_scores: HashMap<i32, (i32, Journey)> = start_threads.into_iter().filter_map(|(id, th)| {
+        let js = th.join().unwrap();
+        let best = Journey::best(&js, p.origin_time).map(|(score, j)| (id, (score, j.clone())));
+        best
+    }).collect();
+
+    // The walk starts at the same time of day every day. Journeys home are asked for once per stop area,
+    // from when the first chain ending there is done, and each chain picks the best after its own walk.
+    let ends: Vec<(Chain, i32, Journey, TimeStamp)> = chains.into_iter()
+        .filter_map(|c| start_scores.get(&c.days[0].src).map(|&(s, ref j)| (c, s, j.clone())))
+        .map(|(c, s, j)| {
+            let time = j.arrtime + ::chrono::Duration::days((days - 1) as i64) + walk_duration(c.days.last().unwrap(), p.walk_speed);
+            (c, s, j, time)
+        }).collect();
+    let mut end_tocheck: HashMap<i32, TimeStamp> = HashMap::new();
+    for &(ref c, _, _, time) in &ends {
+        let t = end_tocheck.entry(c.days.last().unwrap().dest).or_insert(time);
+        if time < *t { *t = time; }
+    }
+    let end_threads: Vec<(i32, _)> = end_tocheck.into_iter().map(|(id, time)| {
+        let (sa1, sa2) = (stopareas[&id].clone(), p.dest_sa.clone());
+        (id, ::std::thread::spawn(move || { ask_journeys(&sa1, &sa2, time) }))
+    }).collect();
+    let end_journeys: HashMap<i32, Vec<Journey>> = end_threads.into_iter().map(|(id, th)| (id, th.join().unwrap())).collect();
+    let mut trips: Vec<(i32, Chain, Journey, Journey)> = ends.into_iter().filter_map(|(c, s, j, time)| {
+        let (es, ej) = if let Some(x) = Journey::best(&end_journeys[&c.days.last().unwrap().dest], time) { x } else { return None };
+        let score = s + es - c.days[0].srcdist - c.days.last().unwrap().destdist;
+        Some((score, c, j, ej.clone()))
+    }).collect();
+    trips.sort_by(|a, b| b.0.cmp(&a.0));
+
+    let mut skip = HashSet::new();
+    let mut shown = vec!();
+    for (_, c, origj, destj) in trips {
+        let (src, dest) = (c.days[0].src, c.days.last().unwrap().dest);
+        if skip.contains(&src) || skip.contains(&dest) { continue; }
+        skip.insert(src);
+        skip.insert(dest);
+
+        println!("");
+        println!("{}. {} dagar, från {} till {}", shown.len() + 1, days, stopareas[&src].name, stopareas[&dest].name);
+        println!("  Res {}, från {} kl {} till {} kl {}", origj.duration_as_string(), p.origin_sa.name,
+            time_to_str(origj.deptime, p.origin_time), stopareas[&src].name, time_to_str(origj.arrtime, p.origin_time));
+        for (i, d) in c.days.iter().enumerate() {
+            let from = if i == 0 { stopareas[&src].name.clone() } else { names[&c.places[i - 1]].name.clone() };
+            let to = if i + 1 == days { stopareas[&dest].name.clone() } else {
+                let o = &names[&c.places[i]];
+                format!("{} ({})", o.name, o.kind)
+            };
+            println!("  Dag {}: Gå {:.1} km från {} till {}, på {}", i + 1, to_km(d.dist), from, to, fix_etapp(&d.etapper));
+            let attrs = fix_attributes(d);
+            if attrs.len() > 0 { println!("    {}", attrs); }
+            for n in variant_notes(d, &router.graph.variants) { println!("    {}", n); }
+            let date = date_str(origj.arrtime + ::chrono::Duration::days(i as i64));
+            for n in closure_notes(d, &router.graph.closures, &date) { println!("    {}", n); }
+        }
+        println!("  Res {}, från {} kl {} till {} kl {}", destj.duration_as_string(), stopareas[&dest].name,
+            time_to_str(destj.deptime, p.origin_time), p.dest_sa.name, time_to_str(destj.arrtime, p.origin_time));
+        let sleep = c.places[..days - 1].iter().map(|n| names[n].name.clone()).collect();
+        shown.push(Trip { origj: origj, destj: destj, days: c.days, sleep: sleep });
+        if shown.len() >= 5 { break; }
+    }
+    if shown.len() == 0 { println!("No trips of {} days found", days); }
+    shown
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use utils::{Path, EtappVisit};
+    use super::{walk_days, fits, Chain};
+
+    // A day walking etapp from km a to b, with access walks of the given length at both ends.
+    fn day(etapp: &str, a: f64, b: f64, access: i32) -> Path {
+        Path { traildist: (b - a).abs() as i32, srcdist: access, destdist: access,
+            etapper: vec!(EtappVisit { etapp: etapp.into(), from_km: a, to_km: b }), .. Default::default() }
+    }
+
+    #[test]
+    fn fits_new_places_and_trail() {
+        let c = Chain { days: vec!(day("1_1", 0f64, 10000f64, 100)), places: vec!(7) };
+        assert!(fits(&c, 8, &day("1_1", 10000f64, 20000f64, 100), 0.2));
+        // Back to where the first day ended
+        assert!(!fits(&c, 7, &day("1_1", 10000f64, 20000f64, 100), 0.2));
+        // 3 of 10 km walked the day before
+        assert!(!fits(&c, 8, &day("1_1", 7000f64, 17000f64, 100), 0.2));
+        assert!(fits(&c, 8, &day("1_1", 7000f64, 17000f64, 100), 0.3));
+    }
+
+    #[test]
+    fn three_days() {
+        // Stop areas are nodes 1 and 2, places to sleep 10, 11 and 12.
+        let first = vec!((10, day("1_1", 0f64, 10000f64, 100)), (11, day("1_1", 0f64, 12000f64, 300)));
+        let mut between = HashMap::new();
+        between.insert(10, vec!((12, day("1_1", 10000f64, 20000f64, 0))));
+        between.insert(11, vec!((12, day("1_1", 12000f64, 20000f64, 0)), (10, day("1_1", 12000f64, 10000f64, 0))));
+        let mut last = HashMap::new();
+        last.insert(12, vec!((2, day("1_1", 20000f64, 30000f64, 200))));
+
+        let chains = walk_days(first, &between, &last, 3, 0.2);
+        let places: Vec<Vec<usize>> = chains.iter().map(|c| c.places.clone()).collect();
+        // Least access walking first. 11 -> 10 walks back over the first day and is dropped.
+        assert_eq!(places, vec!(vec!(10, 12, 2), vec!(11, 12, 2)));
+    }
+}
@@ -0,0 +1,103 @@
+pub fn wgs84_to_rt90(lat_deg: f64, lon_deg: f64) -> (f64, f64) {
+    // References:
+    // http://www.lantmateriet.se/globalassets/kartor-och-geografisk-information/gps-och-matning/geodesi/formelsamling/gauss_conformal_projection.pdf 
+    // http://www.lantmateriet.se/sv/Kartor-och-geografisk-information/GPS-och-geodetisk-matning/Om-geodesi/Transformationer/RT-90---SWEREF-99/
+    let f = 1f64/298.257222101f64;
+    let lon0_deg = 15f64 + 48f64/60f64 + 22.624306f64/3600f64;
+    let lon0 = lon0_deg * std::f64::consts::PI / 180f64;
+    let a = 6378137f64;
+    let k0 = 1.00000561024f64;
+    let ffnn = -667.711f64;
+    let ffee = 1500064.274f64;
+
+    let e_2 = f * (2f64 - f);
+    let n = f / (2f64 - f);
+    let a_caret = a * (1f64 + n * n / 4f64 + n * n * n * n / 64f64) / (1f64 + n);
+
+    let aa = e_2;
+    let e_4 = e_2 * e_2;
+    let bb = (5f64 * e_4 - e_4 * e_2)/6f64;
+    let cc = (104f64 * e_4 * e_2 - 45f64 * e_4 * e_4)/120f64;
+    let dd = 1237f64 * e_4 * e_4 / 1260f64;
+
+    let beta1 = n / 2f64 - 2f64 * n * n / 3f64 + 5f64 * n * n * n / 16f64 + 41f64 * n * n * n * n / 180f64;
+    let beta2 = 13f64 * n * n / 48f64 - 3f64 * n * n * n / 5f64 + 557f64 * n * n * n * n / 1440f64;
+    let beta3 = 61f64 * n * n * n / 240f64 - 103f64 * n * n * n * n / 140f64;
+    let beta4 = 49561f64 * n * n * n * n / 161280f64;
+
+    let lat = lat_deg * std::f64::consts::PI / 180f64;
+    let lon = lon_deg * std::f64::consts::PI / 180f64;
+
+    let ls_2 = lat.sin() * lat.sin();
+    let conf_lat = lat - lat.sin() * lat.cos() * (aa + bb * ls_2 + cc * ls_2 * ls_2 + dd * ls_2 * ls_2 * ls_2);
+
+    let lons = lon - lon0;
+    let xip = (conf_lat.tan() / lons.cos()).atan();
+    let etap = (conf_lat.cos() * lons.sin()).atanh();
+
+    let xp = xip + beta1 * (2f64 * xip).sin() * (2f64 * etap).cosh() + beta2 * (4f64 * xip).sin() * (4f64 * etap).cosh()
+         + beta3 * (6f64 * xip).sin() * (6f64 * etap).cosh() + beta4 * (8f64 * xip).sin() * (8f64 * etap).cosh();
+    let yp = etap + beta1 * (2f64 * xip).cos() * (2f64 * etap).sinh() + beta2 * (4f64 * xip).cos() * (4f64 * etap).sinh()
+         + beta3 * (6f64 * xip).cos() * (6f64 * etap).sinh() + beta4 * (8f64 * xip).cos() * (8f64 * etap).sinh();
+    let x = k0 * a_caret * xp + ffnn;
+    let y = k0 * a_caret * yp + ffee;
+    (x, y)
+}
+
+pub fn rt90_to_wgs84(x: f64, y: f64) -> (f64, f64) {
+    // Inverse of wgs84_to_rt90, same references.
+    let f = 1f64/298.257222101f64;
+    let lon0_deg = 15f64 + 48f64/60f64 + 22.624306f64/3600f64;
+    let lon0 = lon0_deg * std::f64::consts::PI / 180f64;
+    let a = 6378137f64;
+    let k0 = 1.00000561024f64;
+    let ffnn = -667.711f64;
+    let ffee = 1500064.274f64;
+
+    let e_2 = f * (2f64 - f);
+    let n = f / (2f64 - f);
+    let a_caret = a * (1f64 + n * n / 4f64 + n * n * n * n / 64f64) / (1f64 + n);
+
+    let e_4 = e_2 * e_2;
+    let e_6 = e_4 * e_2;
+    let e_8 = e_4 * e_4;
+    let aa = e_2 + e_4 + e_6 + e_8;
+    let bb = -(7f64 * e_4 + 17f64 * e_6 + 30f64 * e_8) / 6f64;
+    let cc = (224f64 * e_6 + 889f64 * e_8) / 120f64;
+    let dd = -(4279f64 * e_8) / 1260f64;
+
+    let delta1 = n / 2f64 - 2f64 * n * n / 3f64 + 37f64 * n * n * n / 96f64 - n * n * n * n / 360f64;
+    let delta2 = n * n / 48f64 + n * n * n / 15f64 - 437f64 * n * n * n * n / 1440f64;
+    let delta3 = 17f64 * n * n * n / 480f64 - 37f64 * n * n * n * n / 840f64;
+    let delta4 = 4397f64 * n * n * n * n / 161280f64;
+
+    let xi = (x - ffnn) / (k0 * a_caret);
+    let eta = (y - ffee) / (k0 * a_caret);
+
+    let xip = xi - delta1 * (2f64 * xi).sin() * (2f64 * eta).cosh() - delta2 * (4f64 * xi).sin() * (4f64 * eta).cosh()
+        - delta3 * (6f64 * xi).sin() * (6f64 * eta).cosh() - delta4 * (8f64 * xi).sin() * (8f64 * eta).cosh();
+    let etap = eta - delta1 * (2f64 * xi).cos() * (2f64 * eta).sinh() - delta2 * (4f64 * xi).cos() * (4f64 * eta).sinh()
+        - delta3 * (6f64 * xi).cos() * (6f64 * eta).sinh() - delta4 * (8f64 * xi).cos() * (8f64 * eta).sinh();
+
+    let conf_lat = (xip.sin() / etap.cosh()).asin();
+    let dlon = (etap.sinh() / xip.cos()).atan();
+    let ls_2 = conf_lat.sin() * conf_lat.sin();
+    let lat = conf_lat + conf_lat.sin() * conf_lat.cos() * (aa + bb * ls_2 + cc * ls_2 * ls_2 + dd * ls_2 * ls_2 * ls_2);
+    let lon = lon0 + dlon;
+    (lat * 180f64 / std::f64::consts::PI, lon * 180f64 / std::f64::consts::PI)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{wgs84_to_rt90, rt90_to_wgs84};
+
+    #[test]
+    fn round_trip() {
+        // Malmö, Kullaberg, Ystad, Kivik and Osby
+        for &(lat, lon) in [(55.605, 13.0038), (56.3, 12.4544), (55.4295, 13.82), (55.6833, 14.2167), (56.3807, 13.9946)].iter() {
+            let (x, y) = wgs84_to_rt90(lat, lon);
+            let (lat2, lon2) = rt90_to_wgs84(x, y);
+            assert!((lat - lat2).abs() < 1e-7 && (lon - lon2).abs() < 1e-7, "{} {} gave {} {}", lat, lon, lat2, lon2);
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use std::collections::BTreeMap;
+use rustc_serialize::json::Json;
+use geo::rt90_to_wgs84;
+
+/// Feature properties, e g props.insert("name".into(), name.to_json())
+pub type Properties = BTreeMap<String, Json>;
+
+fn obj(v: Vec<(&str, Json)>) -> Json {
+    Json::Object(v.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+}
+
+// GeoJSON positions are [longitude, latitude] in WGS84.
+fn position(p: (f64, f64)) -> Json {
+    let (lat, lon) = rt90_to_wgs84(p.0, p.1);
+    Json::Array(vec!(Json::F64(lon), Json::F64(lat)))
+}
+
+pub fn point(p: (f64, f64)) -> Json {
+    obj(vec!(("type", Json::String("Point".into())), ("coordinates", position(p))))
+}
+
+pub fn line_string(ps: &[(f64, f64)]) -> Json {
+    obj(vec!(("type", Json::String("LineString".into())), ("coordinates", Json::Array(ps.iter().map(|&p| position(p)).collect()))))
+}
+
+pub fn feature(geometry: Json, properties: Properties) -> Json {
+    obj(vec!(("type", Json::String("Feature".into())), ("geometry", geometry), ("properties", Json::Object(properties))))
+}
+
+pub fn feature_collection(features: Vec<Json>) -> Json {
+    obj(vec!(("type", Json::String("FeatureCollection".into())), ("features", Json::Array(features))))
+}
+
+pub fn write(fname: &str, features: Vec<Json>) {
+    use std::io::Write;
+    write!(std::fs::File::create(fname).unwrap(), "{}", feature_collection(features)).unwrap();
+    println!("Wrote {}", fname);
+}
@@ -1,8 +1,12 @@
 extern crate rustc_serialize;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, BTreeMap};
 use std::io::Read;
 
+pub mod geo;
+pub mod route;
+pub mod geojson;
+pub mod spatial;
 
 #[derive(RustcDecodable, RustcEncodable, Default, Debug, Clone)]
 pub struct StopArea {
@@ -15,17 +19,207 @@ pub struct StopArea {
 #[derive(RustcDecodable, RustcEncodable, Default, Debug, Clone)]
 pub struct Path {
     pub dist: i32, // Total distance (incl dist from & to trail)
+    pub traildist: i32, // Distance on the trail
     pub srcdist: i32, // Distance to trail
     pub destdist: i32, // Distance from trail
-    pub src: i32, // Stoparea (from)
-    pub dest: i32, // Stoparea (to)
-    pub etapp: String, // E g: 5_1;5_2
+    pub ascent: i32, // Meters climbed along the whole walk
+    pub descent: i32,
+    pub time: i32, // Estimated walking time in seconds at route::FLAT_SPEED, see Profile::cost_model
+    pub time_back: i32, // The same, walking from dest to src
+    pub surface: BTreeMap<String, i32>, // Meters of trail on each known surface
+    pub difficulty: i32, // Hardest part of the trail, from 1 to 3, 0 if not known
+    pub features: Vec<String>, // Along the trail, e g "beach"
+    pub detours: Vec<usize>, // Closures whose detour is walked, as indices into GraphFile::closures
+    pub src: i32, // Stoparea (from), -1 for places that are not stop areas
+    pub dest: i32, // Stoparea (to), the same as src for circular paths
+    pub etapper: Vec<EtappVisit>, // In the order they are walked
+    pub geom: Vec<(f64, f64)>, // The whole walk, from stoparea to stoparea
+    pub marks: Vec<PathMark>, // Junctions on the trail, the first and last are where we enter and leave it
+    pub alternative: i32, // 0 for the best path between src and dest, 1 and up for alternatives to it
+}
+
+/// A stretch of one etapp along a path. The etapp is walked backwards
+/// (against its km numbering) if from_km is larger than to_km.
+#[derive(RustcDecodable, RustcEncodable, Default, Debug, Clone)]
+pub struct EtappVisit {
+    pub etapp: String,
+    pub from_km: f64, // Where we enter the etapp, in meters
+    pub to_km: f64, // Where we leave it
+}
+
+#[derive(RustcDecodable, RustcEncodable, Default, Debug, Clone)]
+pub struct PathMark {
+    pub index: usize, // Into Path::geom
+    pub etapp: String,
+    pub km: f64, // Distance along the etapp, in meters
+}
+
+/// Thresholds used when building the route graph, see makeroutegraph/profiles.toml.
+#[derive(RustcDecodable, RustcEncodable, Debug, Clone)]
+pub struct Profile {
+    pub max_edge: f64, // Gaps between track points longer than this break the etapp
+    pub endpoint_link: f64, // Max distance when linking an etapp endpoint to another etapp
+    pub corridor_width: f64, // Etapper closer than this to each other share the same path
+    pub stop_attach: f64, // Max distance between a stop area and the trail
+    pub access_spacing: f64, // Min distance along an etapp between two access points of one stop area
+    pub min_path: f64, // Paths with less walking on the trail than this are skipped
+    pub max_path: f64, // Paths with more walking on the trail than this are skipped
+    pub access_factor: f64, // Skip paths where access walking times this exceeds the path
+    pub road_detour: f64, // Max walking distance along roads, relative to stop_attach
+    pub exclude_stops: Vec<String>, // Skip stop areas with any of these in the name
+    pub cost_model: String, // "distance", "tobler" or "naismith", what paths are shortest in
+    pub alternatives: usize, // Max number of paths between two stop areas
+    pub alt_penalty: f64, // Edges on a path found cost this much more when searching for alternatives
+    pub alt_overlap: f64, // Skip alternatives sharing more than this part of their trail with an earlier path
+    pub loop_reuse: f64, // Skip circular paths walking more than this part of their trail twice
+    pub day_reuse: f64, // Skip days of a multi-day trip walking more than this part of their trail on earlier days
+}
+
+impl Default for Profile {
+    fn default() -> Profile {
+        Profile { max_edge: 1000f64, endpoint_link: 250f64, corridor_width: 25f64,
+            stop_attach: 5000f64, access_spacing: 3000f64,
+            min_path: 1000f64, max_path: 40000f64, access_factor: 2f64, road_detour: 3f64,
+            exclude_stops: vec!(" NO ".into()), // Ta bort närområdestrafik
+            cost_model: "distance".into(), alternatives: 3, alt_penalty: 1.5f64, alt_overlap: 0.5f64,
+            loop_reuse: 0.2f64, day_reuse: 0.2f64 }
+    }
+}
+
+#[derive(RustcDecodable, RustcEncodable, Debug, Clone)]
+pub struct PathsFile {
+    pub profile: String,
+    pub settings: Profile,
+    pub paths: Vec<Path>,
+}
+
+/// Bump when the layout of GraphFile changes.
+pub const GRAPH_VERSION: i32 = 6;
+
+#[derive(RustcDecodable, RustcEncodable, Debug, Clone)]
+pub struct GraphNode {
+    pub pos: (f64, f64),
+    pub etapp: Option<String>, // None for stop areas
+    pub km: f64, // Distance along the etapp, in meters
+    pub stoparea: Option<i32>,
+}
+
+#[derive(RustcDecodable, RustcEncodable, Debug, Clone)]
+pub struct GraphEdge {
+    pub a: usize, // Index into nodes
+    pub b: usize,
+    pub dist: f64,
+    pub geom: Vec<(f64, f64)>, // From a to b, including both ends. Empty if a straight line.
+    pub ascent: f64, // Walking from a to b, in meters
+    pub descent: f64,
+    pub time_ab: f64, // Estimated walking time in seconds, from a to b
+    pub time_ba: f64,
+    pub detour: Option<usize>, // Index into closures, for detours only open while that closure is
+    pub surface: BTreeMap<String, f64>, // Meters on each known surface, e g "asphalt", "gravel" or "path"
+    pub difficulty: i32, // Hardest part, from 1 for easy to 3 for hard, 0 if not known
+    pub features: Vec<String>, // E g "beach"
+}
+
+/// The contracted trail graph, as written by makeroutegraph to graph.json.
+/// Edges between a stop area and a trail node are access walks.
+#[derive(RustcDecodable, RustcEncodable, Debug, Clone)]
+pub struct GraphFile {
+    pub version: i32,
+    pub profile: String,
+    pub settings: Profile,
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+    pub variants: Vec<Variant>,
+    pub closures: Vec<Closure>,
+}
+
+/// A stretch of an etapp closed between two dates, both included.
+#[derive(RustcDecodable, RustcEncodable, Debug, Clone)]
+pub struct Closure {
+    pub etapp: String,
+    pub from_km: f64, // In meters
+    pub to_km: f64,
+    pub from_date: String, // As "yyyy-mm-dd"
+    pub to_date: String,
+    pub reason: String,
+    pub detour: Vec<(f64, f64)>, // Walk around the closed stretch, empty if there is none
+}
+
+impl Closure {
+    pub fn active(&self, date: &str) -> bool { &*self.from_date <= date && date <= &*self.to_date }
+
+    /// Whether walking etapp from meter a to b passes through or into the closed stretch.
+    pub fn touches(&self, etapp: &str, a: f64, b: f64) -> bool {
+        etapp == self.etapp && a.min(b) <= self.to_km.max(self.from_km) && a.max(b) >= self.from_km.min(self.to_km)
+    }
+}
+
+/// An A/B variant etapp, e g "5_1A", walked instead of part of the main trail.
+#[derive(RustcDecodable, RustcEncodable, Debug, Clone)]
+pub struct Variant {
+    pub etapp: String,
+    pub main: String, // The etapp it is a variant of, e g "5_1"
+    pub leaves: Option<(String, f64)>, // Etapp and meters along it where the variant leaves the main trail, None if not close to it
+    pub joins: Option<(String, f64)>, // Where it joins the main trail again
+}
+
+impl Variant {
+    /// The stretches of main trail this variant replaces, as (etapp, from meter, to meter).
+    /// If it leaves and joins on different etapper, the main trail is taken to run
+    /// from the end of the earlier etapp to the start of the later one, whichever
+    /// way the variant itself runs.
+    pub fn replaces(&self) -> Vec<(String, f64, f64)> {
+        match (&self.leaves, &self.joins) {
+            (&Some((ref e1, k1)), &Some((ref e2, k2))) if e1 == e2 => vec!((e1.clone(), k1.min(k2), k1.max(k2))),
+            (&Some(ref a), &Some(ref b)) => {
+                let (a, b) = if etapp_number(&a.0) <= etapp_number(&b.0) { (a, b) } else { (b, a) };
+                vec!((a.0.clone(), a.1, std::f64::INFINITY), (b.0.clone(), 0f64, b.1))
+            }
+            _ => vec!(),
+        }
+    }
 }
 
 impl Path {
     pub fn reverse(&mut self) {
         std::mem::swap(&mut self.srcdist, &mut self.destdist);
+        std::mem::swap(&mut self.ascent, &mut self.descent);
+        std::mem::swap(&mut self.time, &mut self.time_back);
         std::mem::swap(&mut self.src, &mut self.dest);
+        self.geom.reverse();
+        self.marks.reverse();
+        self.etapper.reverse();
+        for v in &mut self.etapper { std::mem::swap(&mut v.from_km, &mut v.to_km); }
+        let l = self.geom.len();
+        for m in &mut self.marks { m.index = l - 1 - m.index; }
+    }
+}
+
+/// The main etapp of a variant, e g "5_1A" gives "5_1". None if etapp is not a variant.
+pub fn variant_of(etapp: &str) -> Option<&str> {
+    if etapp.ends_with(|c: char| c.is_alphabetic()) { Some(etapp.trim_right_matches(|c: char| c.is_alphabetic())) } else { None }
+}
+
+/// Led and etapp number, e g "5_12A" gives (5, 12). Etapper follow each other in this order.
+pub fn etapp_number(etapp: &str) -> (i32, i32) {
+    let mut s = variant_of(etapp).unwrap_or(etapp).splitn(2, "_").map(|x| x.parse::<i32>().unwrap_or(0));
+    (s.next().unwrap_or(0), s.next().unwrap_or(0))
+}
+
+/// Escapes text for XML, SVG and HTML, in text and quoted attributes.
+pub fn xml_escape(s: &str) -> String {
+    s.replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;").replace("\"", "&quot;")
+}
+
+/// Name of the led an etapp belongs to, e g "5_1A" gives "Öresundsleden".
+pub fn led_name(etapp: &str) -> &'static str {
+    match etapp.split("_").next().unwrap() {
+        "1" => "Kust-kustleden",
+        "2" => "Nord-sydleden",
+        "3" => "Ås-åsleden",
+        "4" => "Österlenleden",
+        "5" => "Öresundsleden",
+        _ => panic!("Unknown led {}", etapp),
     }
 }
 
@@ -40,5 +234,15 @@ pub fn read_paths() -> Vec<Path> {
     let mut f = std::fs::File::open("../data/paths.json").unwrap();
     let mut s = String::new();
     f.read_to_string(&mut s).unwrap();
-    rustc_serialize::json::decode(&s).unwrap()
+    let p: PathsFile = rustc_serialize::json::decode(&s).unwrap();
+    p.paths
+}
+
+pub fn read_graph() -> GraphFile {
+    let mut f = std::fs::File::open("../data/graph.json").unwrap();
+    let mut s = String::new();
+    f.read_to_string(&mut s).unwrap();
+    let g: GraphFile = rustc_serialize::json::decode(&s).unwrap();
+    if g.version != GRAPH_VERSION { panic!("graph.json has version {}, expected {}. Run makeroutegraph again.", g.version, GRAPH_VERSION) }
+    g
 }
@@ -0,0 +1,422 @@
+use std::collections::{BinaryHeap, BTreeMap, BTreeSet, HashMap, HashSet};
+use std::cmp;
+use std::cmp::Ordering;
+use {GraphFile, GraphNode, GraphEdge, Path, PathMark, EtappVisit};
+use spatial::Grid;
+
+// Reversed on distance, so that BinaryHeap pops the closest node first.
+#[derive(PartialEq)]
+struct State(f64, usize);
+
+impl Eq for State {}
+
+impl PartialOrd for State {
+    fn partial_cmp(&self, rhs: &State) -> Option<Ordering> { Some(self.cmp(rhs)) }
+}
+
+impl Ord for State {
+    fn cmp(&self, rhs: &State) -> Ordering { rhs.0.partial_cmp(&self.0).unwrap_or(Ordering::Equal) }
+}
+
+/// Walking speed on flat ground, in meters per hour. Estimated times are for
+/// this speed, scale them for faster or slower walkers.
+pub const FLAT_SPEED: f64 = 5000f64;
+
+/// Estimated time in seconds to walk dist meters while climbing rise meters
+/// (negative when walking downhill), with the given cost model.
+pub fn walk_time(model: &str, dist: f64, rise: f64) -> f64 {
+    let flat = dist * 3600f64 / FLAT_SPEED;
+    if dist <= 0f64 { return 0f64; }
+    match model {
+        "distance" => flat,
+        // Tobler's hiking function, relative to its speed on flat ground
+        "tobler" => flat * (-3.5f64 * 0.05).exp() / (-3.5f64 * (rise / dist + 0.05).abs()).exp(),
+        // Naismith's rule, an extra hour per 600 m of ascent
+        "naismith" => flat + rise.max(0f64) * 3600f64 / 600f64,
+        _ => panic!("Unknown cost model {:?}", model),
+    }
+}
+
+/// Limits for a search from one stop area.
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    pub min_trail: f64, // Distance on the trail, excluding walks to and from it
+    pub max_trail: f64,
+    pub max_dist: Option<f64>, // Total distance, including walks to and from the trail
+    pub max_access: Option<f64>, // Longest walk to the trail at the start, or from it at the end
+    pub avoid: HashSet<String>, // Etapper not to walk on
+    pub alternatives: usize, // Max number of paths to every stop area, 0 and 1 both give just the best one
+    pub variants: String, // Where the trail has A/B variants, prefer "main" or "variants", anything else for either
+    pub date: Option<String>, // Walk on this day, "yyyy-mm-dd", avoiding the closures then. None ignores closures.
+    pub max_surface: HashMap<String, f64>, // Skip paths with more of their trail than this on a surface, as a part from 0 to 1
+    pub max_difficulty: Option<i32>, // Trail harder than this is not walked
+    pub features: Vec<String>, // Skip paths without each of these somewhere along the trail
+}
+
+/// Edges on the kind of route not preferred by Query::variants cost this much more.
+pub const VARIANT_DETOUR: f64 = 2f64;
+
+/// Stop-to-stop searches on the graph written by makeroutegraph.
+pub struct Router {
+    pub graph: GraphFile,
+    adj: Vec<Vec<(usize, usize)>>, // (neighbour, edge) for every node
+    max_access: f64, // Longest walk between a stop area and the trail
+    etapp_names: Vec<String>,
+    edge_etapps: Vec<Option<usize>>, // The etapp every edge runs along, None for links between etapper, detours and access walks
+    on_variant: Vec<bool>, // Edge is along an A/B variant etapp
+    replaced: Vec<bool>, // Edge is along main trail that a variant is an alternative to
+    closed_by: Vec<Vec<usize>>, // Closures covering (part of) every edge, as indices into graph.closures
+    trail_index: Grid<usize>, // Trail nodes with edges, for finding the closest to a place
+}
+
+impl Router {
+    pub fn new(graph: GraphFile) -> Router {
+        let mut adj = vec![vec!(); graph.nodes.len()];
+        let mut max_access = 0f64;
+        let (mut etapp_names, mut edge_etapps) = (vec!(), vec!());
+        let mut ids = HashMap::new();
+        for (i, e) in graph.edges.iter().enumerate() {
+            adj[e.a].push((e.b, i));
+            adj[e.b].push((e.a, i));
+            if graph.nodes[e.a].stoparea.is_some() || graph.nodes[e.b].stoparea.is_some() {
+                if e.dist > max_access { max_access = e.dist; }
+            }
+            edge_etapps.push(match (&graph.nodes[e.a].etapp, &graph.nodes[e.b].etapp) {
+                (&Some(ref x), &Some(ref y)) if x == y && e.detour.is_none() => Some(*ids.entry(x.clone()).or_insert_with(|| {
+                    etapp_names.push(x.clone());
+                    etapp_names.len() - 1
+                })),
+                _ => None,
+            });
+        }
+        let on_variant = edge_etapps.iter().map(|et| et.map(|i| ::variant_of(&etapp_names[i]).is_some()).unwrap_or(false)).collect();
+        let replaces: Vec<(String, f64, f64)> = graph.variants.iter().flat_map(|v| v.replaces().into_iter()).collect();
+        let replaced = graph.edges.iter().zip(edge_etapps.iter()).map(|(e, et)| match *et {
+            Some(i) => {
+                let (k1, k2) = (graph.nodes[e.a].km, graph.nodes[e.b].km);
+                replaces.iter().any(|r| r.0 == etapp_names[i] && k1.min(k2) >= r.1 && k1.max(k2) <= r.2)
+            }
+            None => false,
+        }).collect();
+        let closed_by = graph.edges.iter().zip(edge_etapps.iter()).map(|(e, et)| match *et {
+            Some(i) => {
+                let (k1, k2) = (graph.nodes[e.a].km, graph.nodes[e.b].km);
+                graph.closures.iter().enumerate().filter(|&(_, c)| c.etapp == etapp_names[i] &&
+                    k1.min(k2) < c.from_km.max(c.to_km) && k1.max(k2) > c.from_km.min(c.to_km)).map(|(ci, _)| ci).collect()
+            }
+            None => vec!(),
+        }).collect();
+        let trail_index = Grid::from_iter(500f64, (0..graph.nodes.len())
+            .filter(|&i| graph.nodes[i].etapp.is_some() && adj[i].len() > 0).map(|i| (graph.nodes[i].pos, i)));
+        Router { graph: graph, adj: adj, max_access: max_access, etapp_names: etapp_names, edge_etapps: edge_etapps,
+            on_variant: on_variant, replaced: replaced, closed_by: closed_by, trail_index: trail_index }
+    }
+
+    pub fn stop_nodes(&self) -> Vec<usize> {
+        (0..self.graph.nodes.len()).filter(|&n| self.graph.nodes[n].stoparea.is_some()).collect()
+    }
+
+    /// Adds a place off the trail, e g somewhere to sleep, with a straight walk to the closest
+    /// trail node. Returns its node, or None if the trail is further away than settings.stop_attach.
+    pub fn add_place(&mut self, pos: (f64, f64)) -> Option<usize> {
+        let (nn, dist) = match self.trail_index.closest(pos, |_| true) { Some(c) => c, None => return None };
+        if dist > self.graph.settings.stop_attach { return None; }
+
+        let (n, e) = (self.graph.nodes.len(), self.graph.edges.len());
+        let t = walk_time("distance", dist, 0f64);
+        self.graph.nodes.push(GraphNode { pos: pos, etapp: None, km: 0f64, stoparea: None });
+        self.graph.edges.push(GraphEdge { a: n, b: nn, dist: dist, geom: vec!(), ascent: 0f64, descent: 0f64, time_ab: t, time_ba: t, detour: None,
+            surface: BTreeMap::new(), difficulty: 0, features: vec!() });
+        self.adj.push(vec!((nn, e)));
+        self.adj[nn].push((n, e));
+        self.edge_etapps.push(None);
+        self.on_variant.push(false);
+        self.replaced.push(false);
+        self.closed_by.push(vec!());
+        if dist > self.max_access { self.max_access = dist; }
+        Some(n)
+    }
+
+    // What it costs to walk edge e starting at node from: its length or its walking time.
+    fn cost(&self, e: usize, from: usize) -> f64 {
+        if self.graph.settings.cost_model == "distance" { self.graph.edges[e].dist } else { self.time(e, from) }
+    }
+
+    // Extra cost factor for edge e, given the preference for main trail or variants.
+    fn preference(&self, e: usize, q: &Query) -> f64 {
+        match &*q.variants {
+            "main" if self.on_variant[e] => VARIANT_DETOUR,
+            "variants" if self.replaced[e] => VARIANT_DETOUR,
+            _ => 1f64,
+        }
+    }
+
+    fn time(&self, e: usize, from: usize) -> f64 {
+        let ee = &self.graph.edges[e];
+        if ee.a == from { ee.time_ab } else { ee.time_ba }
+    }
+
+    // Not an access walk
+    fn is_trail(&self, e: usize) -> bool {
+        let e = &self.graph.edges[e];
+        self.graph.nodes[e.a].etapp.is_some() && self.graph.nodes[e.b].etapp.is_some()
+    }
+
+    fn other_end(&self, e: usize, n: usize) -> usize {
+        let e = &self.graph.edges[e];
+        if e.a == n { e.b } else { e.a }
+    }
+
+    // Geometry of edge e, walked starting at node from, including both ends.
+    fn walk_geom(&self, e: usize, from: usize) -> Vec<(f64, f64)> {
+        let ee = &self.graph.edges[e];
+        if ee.geom.len() == 0 { return vec!(self.graph.nodes[from].pos, self.graph.nodes[self.other_end(e, from)].pos) }
+        if ee.a == from { ee.geom.clone() } else { ee.geom.iter().rev().cloned().collect() }
+    }
+
+    /// Paths from the stop area at node src to every other stop area, within the limits of the query.
+    /// With q.alternatives above one, alternative paths are found by making the edges of
+    /// the paths already found more expensive and searching again. Alternatives sharing too
+    /// much trail (settings.alt_overlap) with an earlier path to the same stop area are skipped.
+    pub fn paths_from(&self, src: usize, q: &Query) -> Vec<Path> {
+        let mut penalty = vec![1f64; self.graph.edges.len()];
+        let mut found: Vec<(Path, Vec<usize>)> = vec!();
+        for _ in 0..cmp::max(q.alternatives, 1) {
+            let best = self.search(src, q, &penalty);
+            let mut used = HashSet::new();
+            for n in self.stop_nodes() {
+                if n == src || best[n].is_none() { continue; }
+                let edges = self.backtrack(src, n, &best);
+                let mut p = match self.make_path(src, n, &edges) { Some(p) => p, None => continue };
+                if !self.within_limits(&p, q) { continue; }
+                let ids: Vec<usize> = edges.iter().map(|x| x.0).collect();
+                let (similar, earlier) = {
+                    let earlier: Vec<&Vec<usize>> = found.iter().filter(|f| f.0.dest == p.dest).map(|f| &f.1).collect();
+                    (earlier.iter().any(|f| self.shared(&ids, f) > self.graph.settings.alt_overlap), earlier.len())
+                };
+                if similar { continue; }
+                p.alternative = earlier as i32;
+                used.extend(ids.iter().cloned());
+                found.push((p, ids));
+            }
+            if used.len() == 0 { break; }
+            for e in used { penalty[e] *= self.graph.settings.alt_penalty; }
+        }
+        found.into_iter().map(|f| f.0).collect()
+    }
+
+    /// Circular paths starting and ending at the stop area at node src, within the limits of the query.
+    /// Every edge outside the tree of shortest paths from src closes a loop: out to one end of the edge
+    /// and back from the other. Loops walking more than settings.loop_reuse of their trail twice are
+    /// skipped, and so are loops too similar to a better one, keeping at most q.alternatives of them.
+    pub fn loops_from(&self, src: usize, q: &Query) -> Vec<Path> {
+        let best = self.search(src, q, &vec![1f64; self.graph.edges.len()]);
+        let reached = |n: usize| best[n].is_some() && (n == src || self.graph.nodes[n].etapp.is_some());
+        let (closed, avoid) = (self.closed(q), self.avoided(q));
+        let mut candidates = vec!();
+        for (e, ee) in self.graph.edges.iter().enumerate() {
+            if !reached(ee.a) || !reached(ee.b) || !self.walkable(e, q, &closed, &avoid) { continue; }
+            if best[ee.a].unwrap().2 == e || best[ee.b].unwrap().2 == e { continue; }
+            let (out, back) = (self.backtrack(src, ee.a, &best), self.backtrack(src, ee.b, &best));
+            let same = out.iter().zip(back.iter()).take_while(|&(x, y)| x.0 == y.0).count();
+            let mut edges = out.clone();
+            edges.push((e, ee.a));
+            edges.extend(back.iter().rev().map(|&(e, from)| (e, self.other_end(e, from))));
+            let p = match self.make_path(src, src, &edges) { Some(p) => p, None => continue };
+            if !self.within_limits(&p, q) { continue; }
+            let twice = out[..same].iter().filter(|x| self.is_trail(x.0)).map(|x| self.graph.edges[x.0].dist).fold(0f64, |a, b| a + b);
+            let reuse = 2f64 * twice / p.traildist as f64;
+            if reuse > self.graph.settings.loop_reuse { continue; }
+            candidates.push((reuse, p, edges.iter().map(|x| x.0).collect::<Vec<_>>()));
+        }
+        candidates.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let mut found: Vec<(Path, Vec<usize>)> = vec!();
+        for (_, mut p, ids) in candidates {
+            if found.len() >= cmp::max(q.alternatives, 1) { break; }
+            if found.iter().any(|f| self.shared(&ids, &f.1) > self.graph.settings.alt_overlap) { continue; }
+            p.alternative = found.len() as i32;
+            found.push((p, ids));
+        }
+        found.into_iter().map(|f| f.0).collect()
+    }
+
+    /// Paths from node src to each of the target nodes, within the limits of the query.
+    /// Both may be stop areas or places added with add_place.
+    pub fn paths_to(&self, src: usize, targets: &[usize], q: &Query) -> Vec<(usize, Path)> {
+        let best = self.search(src, q, &vec![1f64; self.graph.edges.len()]);
+        targets.iter().filter(|&&n| n != src && best[n].is_some())
+            .filter_map(|&n| self.make_path(src, n, &self.backtrack(src, n, &best)).map(|p| (n, p)))
+            .filter(|x| self.within_limits(&x.1, q)).collect()
+    }
+
+    // Dijkstra from src, with the cost of every edge multiplied by its penalty and preference.
+    // Returns cost, distance and the edge we came through, for every node reached.
+    // Nodes further away than any path within q.max_trail could end are not searched.
+    // Stretches closed on q.date are not walked, and detours only then.
+    fn search(&self, src: usize, q: &Query, penalty: &[f64]) -> Vec<Option<(f64, f64, usize)>> {
+        let src_access = self.adj[src].iter().map(|&(_, e)| self.graph.edges[e].dist).fold(0f64, |a, b| a.max(b));
+        let max = q.max_trail + src_access + q.max_access.map(|m| m.min(self.max_access)).unwrap_or(self.max_access);
+        let max = q.max_dist.map(|m| m.min(max)).unwrap_or(max);
+        let (closed, avoid) = (self.closed(q), self.avoided(q));
+        let mut best: Vec<Option<(f64, f64, usize)>> = vec![None; self.graph.nodes.len()];
+        let mut heap = BinaryHeap::new();
+        best[src] = Some((0f64, 0f64, usize::max_value()));
+        heap.push(State(0f64, src));
+        while let Some(State(c, n)) = heap.pop() {
+            let d = best[n].unwrap().1;
+            if c > best[n].unwrap().0 { continue; }
+            // Stop areas and places are only used as start and end points, never to walk between two access points.
+            if n != src && self.graph.nodes[n].etapp.is_none() { continue; }
+            for &(nn, e) in &self.adj[n] {
+                if !self.walkable(e, q, &closed, &avoid) { continue; }
+                let nd = d + self.graph.edges[e].dist;
+                if nd > max { continue; }
+                let nc = c + self.cost(e, n) * penalty[e] * self.preference(e, q);
+                if best[nn].map(|(oc, _, _)| oc <= nc).unwrap_or(false) { continue; }
+                best[nn] = Some((nc, nd, e));
+                heap.push(State(nc, nn));
+            }
+        }
+        best
+    }
+
+    // Whether every etapp is in q.avoid, by etapp id.
+    fn avoided(&self, q: &Query) -> Vec<bool> {
+        self.etapp_names.iter().map(|n| q.avoid.contains(n)).collect()
+    }
+
+    // Whether every closure is active on q.date, by index into graph.closures.
+    fn closed(&self, q: &Query) -> Vec<bool> {
+        self.graph.closures.iter().map(|c| q.date.as_ref().map(|d| c.active(d)).unwrap_or(false)).collect()
+    }
+
+    // Whether edge e may be walked at all, given the closures active and the etapper avoided.
+    // Closed stretches, access walks longer than q.max_access and trail harder than
+    // q.max_difficulty are not walked. Detours are walked only while their closure is active.
+    fn walkable(&self, e: usize, q: &Query, closed: &[bool], avoid: &[bool]) -> bool {
+        if self.edge_etapps[e].map(|et| avoid[et]).unwrap_or(false) { return false; }
+        let ee = &self.graph.edges[e];
+        let access = self.graph.nodes[ee.a].stoparea.is_some() || self.graph.nodes[ee.b].stoparea.is_some();
+        if access && q.max_access.map(|m| ee.dist > m).unwrap_or(false) { return false; }
+        if q.max_difficulty.map(|m| ee.difficulty > m).unwrap_or(false) { return false; }
+        if self.closed_by[e].iter().any(|&c| closed[c]) { return false; }
+        ee.detour.map(|c| closed[c]).unwrap_or(true)
+    }
+
+    // (edge, node we walk it from), from src to dest.
+    fn backtrack(&self, src: usize, dest: usize, best: &[Option<(f64, f64, usize)>]) -> Vec<(usize, usize)> {
+        let mut edges = vec!();
+        let mut n = dest;
+        while n != src {
+            let e = best[n].unwrap().2;
+            n = self.other_end(e, n);
+            edges.push((e, n));
+        }
+        edges.reverse();
+        edges
+    }
+
+    fn within_limits(&self, p: &Path, q: &Query) -> bool {
+        let trail = p.traildist as f64;
+        let access = (p.srcdist + p.destdist) as f64;
+        trail >= q.min_trail && trail <= q.max_trail &&
+            q.max_dist.map(|m| p.dist as f64 <= m).unwrap_or(true) &&
+            q.max_access.map(|m| cmp::max(p.srcdist, p.destdist) as f64 <= m).unwrap_or(true) &&
+            access * self.graph.settings.access_factor <= p.dist as f64 &&
+            q.max_surface.iter().all(|(s, &m)| p.surface.get(s).map(|&d| d as f64 <= m * trail).unwrap_or(true)) &&
+            q.features.iter().all(|f| p.features.contains(f))
+    }
+
+    // How much of the trail walked along edges a is also walked along edges b, from 0 to 1.
+    fn shared(&self, a: &[usize], b: &[usize]) -> f64 {
+        let b: HashSet<usize> = b.iter().cloned().collect();
+        let (mut total, mut both) = (0f64, 0f64);
+        for &e in a.iter().filter(|&&e| self.is_trail(e)) {
+            total += self.graph.edges[e].dist;
+            if b.contains(&e) { both += self.graph.edges[e].dist; }
+        }
+        if total <= 0f64 { 1f64 } else { both / total }
+    }
+
+    fn make_path(&self, src: usize, dest: usize, edges: &[(usize, usize)]) -> Option<Path> {
+        if edges.len() < 2 { return None; }
+        let mut etapper: Vec<EtappVisit> = vec!();
+        let mut last = None;
+        for &(e, from) in edges {
+            if let Some(et) = self.edge_etapps[e] {
+                let (k1, k2) = (self.graph.nodes[from].km, self.graph.nodes[self.other_end(e, from)].km);
+                if last == Some(et) {
+                    etapper.last_mut().unwrap().to_km = k2;
+                } else {
+                    etapper.push(EtappVisit { etapp: self.etapp_names[et].clone(), from_km: k1, to_km: k2 });
+                }
+            }
+            last = self.edge_etapps[e];
+        }
+        let mut geom = vec!(self.graph.nodes[src].pos);
+        let mut marks = vec!();
+        for &(e, from) in edges {
+            geom.extend(self.walk_geom(e, from).into_iter().skip(1));
+            let n = &self.graph.nodes[self.other_end(e, from)];
+            if let Some(ref et) = n.etapp {
+                marks.push(PathMark { index: geom.len() - 1, etapp: et.clone(), km: n.km });
+            }
+        }
+
+        let (mut surface, mut difficulty, mut features) = (BTreeMap::new(), 0, BTreeSet::new());
+        for &(e, _) in edges {
+            let ee = &self.graph.edges[e];
+            for (k, &v) in &ee.surface { *surface.entry(k.clone()).or_insert(0f64) += v; }
+            difficulty = cmp::max(difficulty, ee.difficulty);
+            features.extend(ee.features.iter().cloned());
+        }
+
+        let (fe, le) = (edges[0].0, edges[edges.len() - 1].0);
+        let sum = |f: &Fn(usize, usize) -> f64| edges.iter().map(|&(e, from)| f(e, from)).fold(0f64, |a, b| a + b) as i32;
+        let climb = |e: usize, from: usize, up: bool| {
+            let ee = &self.graph.edges[e];
+            if (ee.a == from) == up { ee.ascent } else { ee.descent }
+        };
+        Some(Path {
+            dist: sum(&|e, _| self.graph.edges[e].dist),
+            traildist: sum(&|e, _| if self.is_trail(e) { self.graph.edges[e].dist } else { 0f64 }),
+            srcdist: self.graph.edges[fe].dist as i32,
+            destdist: self.graph.edges[le].dist as i32,
+            ascent: sum(&|e, from| climb(e, from, true)),
+            descent: sum(&|e, from| climb(e, from, false)),
+            time: sum(&|e, from| self.time(e, from)),
+            time_back: sum(&|e, from| self.time(e, self.other_end(e, from))),
+            surface: surface.into_iter().map(|(k, v)| (k, v.round() as i32)).collect(),
+            difficulty: difficulty,
+            features: features.into_iter().collect(),
+            detours: edges.iter().filter_map(|&(e, _)| self.graph.edges[e].detour).collect(),
+            src: self.graph.nodes[src].stoparea.unwrap_or(-1),
+            dest: self.graph.nodes[dest].stoparea.unwrap_or(-1),
+            etapper: etapper,
+            geom: geom,
+            marks: marks,
+            alternative: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::walk_time;
+
+    fn close(a: f64, b: f64) -> bool { (a - b).abs() < 1e-6 }
+
+    #[test]
+    fn cost_models() {
+        // 1 km takes 720 s at FLAT_SPEED, the same with every model on flat ground.
+        for m in ["distance", "tobler", "naismith"].iter() { assert!(close(walk_time(m, 1000f64, 0f64), 720f64)); }
+        assert!(close(walk_time("naismith", 10000f64, 600f64), 7200f64 + 3600f64));
+        assert!(close(walk_time("naismith", 10000f64, -600f64), 7200f64));
+        // Tobler is fastest walking slightly downhill, at 5%
+        let (down, up) = (walk_time("tobler", 1000f64, -50f64), walk_time("tobler", 1000f64, 50f64));
+        assert!(close(down, 720f64 * (-0.175f64).exp()));
+        assert!(close(up, 720f64 * 0.175f64.exp()));
+        assert!(down < 720f64 && 720f64 < up);
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+
+fn dist(a: (f64, f64), b: (f64, f64)) -> f64 { ((a.0 - b.0) * (a.0 - b.0) + (a.1 - b.1) * (a.1 - b.1)).sqrt() }
+
+/// Uniform grid over (RT90) coordinates, for nearest-node queries.
+/// Items are bucketed by cell, and queries search rings of cells outwards
+/// from the query point until no closer item can exist.
+pub struct Grid<T> {
+    cell: f64,
+    cells: HashMap<(i32, i32), Vec<((f64, f64), T)>>,
+    min: (i32, i32),
+    max: (i32, i32),
+}
+
+impl<T: Copy> Grid<T> {
+    pub fn new(cell: f64) -> Grid<T> {
+        Grid { cell: cell, cells: HashMap::new(), min: (i32::max_value(), i32::max_value()), max: (i32::min_value(), i32::min_value()) }
+    }
+
+    pub fn from_iter<I: Iterator<Item=((f64, f64), T)>>(cell: f64, i: I) -> Grid<T> {
+        let mut g = Grid::new(cell);
+        for (p, t) in i { g.insert(p, t) }
+        g
+    }
+
+    fn cell_of(&self, p: (f64, f64)) -> (i32, i32) { ((p.0 / self.cell).floor() as i32, (p.1 / self.cell).floor() as i32) }
+
+    pub fn insert(&mut self, p: (f64, f64), t: T) {
+        let c = self.cell_of(p);
+        self.min = (::std::cmp::min(self.min.0, c.0), ::std::cmp::min(self.min.1, c.1));
+        self.max = (::std::cmp::max(self.max.0, c.0), ::std::cmp::max(self.max.1, c.1));
+        self.cells.entry(c).or_insert(vec!()).push((p, t));
+    }
+
+    // Calls f for every item in the cells at exactly ring distance r from c.
+    fn ring<F: FnMut(&((f64, f64), T))>(&self, c: (i32, i32), r: i32, mut f: F) {
+        for x in (c.0 - r)..(c.0 + r + 1) {
+            for y in (c.1 - r)..(c.1 + r + 1) {
+                if (x - c.0).abs() != r && (y - c.1).abs() != r { continue; }
+                if let Some(v) = self.cells.get(&(x, y)) { for i in v { f(i) } }
+            }
+        }
+    }
+
+    // True if ring r around c covers every non-empty cell.
+    fn covers(&self, c: (i32, i32), r: i32) -> bool {
+        c.0 - r <= self.min.0 && c.1 - r <= self.min.1 && c.0 + r >= self.max.0 && c.1 + r >= self.max.1
+    }
+
+    /// The k items closest to p that pass the filter, closest first.
+    pub fn k_nearest<F: Fn(T) -> bool>(&self, p: (f64, f64), k: usize, filter: F) -> Vec<(T, f64)> {
+        let mut r = vec!();
+        if k == 0 || self.cells.is_empty() { return r; }
+        let c = self.cell_of(p);
+        let mut ring = 0;
+        loop {
+            self.ring(c, ring, |&(pp, t)| if filter(t) { r.push((t, dist(p, pp))) });
+            r.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+            r.truncate(k);
+            // Everything outside the rings searched so far is at least this far away.
+            let reach = (ring as f64) * self.cell;
+            if (r.len() >= k && r[k-1].1 <= reach) || self.covers(c, ring) { return r; }
+            ring += 1;
+        }
+    }
+
+    /// The item closest to p that passes the filter.
+    pub fn closest<F: Fn(T) -> bool>(&self, p: (f64, f64), filter: F) -> Option<(T, f64)> {
+        self.k_nearest(p, 1, filter).pop()
+    }
+
+    /// All items within radius of p, closest first.
+    pub fn within(&self, p: (f64, f64), radius: f64) -> Vec<(T, f64)> {
+        let c = self.cell_of(p);
+        // Items in ring r are at least (r - 1) cells away.
+        let rings = (radius / self.cell).floor() as i32 + 1;
+        let mut r = vec!();
+        for ring in 0..(rings + 1) {
+            self.ring(c, ring, |&(pp, t)| { let d = dist(p, pp); if d <= radius { r.push((t, d)) }});
+            if self.covers(c, ring) { break; }
+        }
+        r.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        r
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Grid, dist};
+
+    // Points spread over 10 x 10 km, the same every run.
+    fn points(n: usize) -> Vec<(f64, f64)> {
+        let mut x: u64 = 12345;
+        let mut next = || {
+            x = x.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (x >> 33) as f64 / (1u64 << 31) as f64 * 10000f64
+        };
+        (0..n).map(|_| { let a = next(); (6150000f64 + a, 1350000f64 + next()) }).collect()
+    }
+
+    fn scan<F: Fn(usize) -> bool>(ps: &[(f64, f64)], p: (f64, f64), filter: F) -> Vec<(usize, f64)> {
+        let mut r: Vec<(usize, f64)> = ps.iter().enumerate().filter(|&(i, _)| filter(i)).map(|(i, &q)| (i, dist(p, q))).collect();
+        r.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        r
+    }
+
+    // Inside the grid, on a cell border, and outside it on either side.
+    const QUERIES: [(f64, f64); 5] = [(6155000f64, 1355000f64), (6151234f64, 1358765f64), (6150500f64, 1350000f64),
+        (6140000f64, 1340000f64), (6170000f64, 1356000f64)];
+
+    #[test]
+    fn k_nearest_matches_scan() {
+        let ps = points(500);
+        let grid = Grid::from_iter(500f64, ps.iter().cloned().zip(0..ps.len()));
+        for &p in QUERIES.iter() {
+            for &k in [1, 3, 20, 600].iter() {
+                let mut want = scan(&ps, p, |i| i % 3 != 0);
+                want.truncate(k);
+                assert_eq!(grid.k_nearest(p, k, |i| i % 3 != 0), want);
+            }
+            assert_eq!(grid.closest(p, |_| true), scan(&ps, p, |_| true).first().cloned());
+            assert_eq!(grid.k_nearest(p, 5, |_| false), vec!());
+            assert_eq!(grid.closest(p, |_| false), None);
+        }
+        let empty: Grid<usize> = Grid::new(500f64);
+        assert_eq!(empty.closest(QUERIES[0], |_| true), None);
+    }
+
+    #[test]
+    fn within_matches_scan() {
+        let ps = points(500);
+        let grid = Grid::from_iter(500f64, ps.iter().cloned().zip(0..ps.len()));
+        for &p in QUERIES.iter() {
+            for &radius in [0f64, 300f64, 500f64, 1000f64, 2750f64, 50000f64].iter() {
+                let want: Vec<(usize, f64)> = scan(&ps, p, |_| true).into_iter().filter(|x| x.1 <= radius).collect();
+                assert_eq!(grid.within(p, radius), want);
+            }
+        }
+    }
+}